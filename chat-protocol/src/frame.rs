@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+
+use crate::scram::{Credentials, KdfParams};
+
+pub use serde_json::Error as DecodeError;
+
+// A stored chat message as seen by clients, `timestamp` is in milliseconds since the epoch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub id: String,
+    pub sender: String,
+    pub body: String,
+    pub timestamp: i64,
+    pub room: String,
+}
+
+// A one-to-one message, `timestamp` is in milliseconds since the epoch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirectMessage {
+    pub id: String,
+    pub from: String,
+    pub to: String,
+    pub body: String,
+    pub timestamp: i64,
+}
+
+// Points at the oldest message a client already has, so older ones can be paged in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryCursor {
+    pub id: String,
+    pub timestamp: i64,
+}
+
+impl From<&ChatMessage> for HistoryCursor {
+    fn from(message: &ChatMessage) -> Self {
+        Self {
+            id: message.id.clone(),
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+// `online` counts the connections currently joined to the room
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomInfo {
+    pub name: String,
+    pub created_by: String,
+    pub online: usize,
+}
+
+// Opaque bearer token for `Resume`, `expires_at` is in milliseconds since the epoch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionToken {
+    pub token: String,
+    pub expires_at: i64,
+}
+
+// A login session of the current user, `current` marks the one this connection uses
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub current: bool,
+}
+
+// Stable reason of an `Error` frame, clients should match on this instead of the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // Malformed or unexpected frame, or a field with an invalid value
+    BadRequest,
+    // The frame needs a logged in connection
+    Unauthenticated,
+    InvalidCredentials,
+    // The account was created before challenge-response login, it logs in once with `LegacyLogin` to migrate
+    AccountOutdated,
+    UsernameTaken,
+    // The session token is unknown, revoked or expired
+    InvalidSession,
+    NotFound,
+    RoomExists,
+    NotInRoom,
+    AlreadyInRoom,
+    // The server can't reach its database right now and recovers on its own once it is back
+    ServiceDegraded,
+    // Something failed on the server, trying again later may work
+    Internal,
+}
+
+// Frames sent by the client, encoded as JSON text frames tagged by "type"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientFrame {
+    Hello { version: u32, features: Vec<String> },
+    Register { username: String, credentials: Credentials },
+    // Starts the challenge-response login, `nonce` is random and fresh for every attempt
+    Login { username: String, nonce: String },
+    // ClientKey XOR HMAC(StoredKey, auth message), base64
+    LoginProof { proof: String },
+    // Answer to `AccountOutdated`, `password` is the SHA-256 hex digest older clients sent. The stored
+    // value is replaced with `credentials` in the same step, so this works once per account
+    LegacyLogin { username: String, password: String, credentials: Credentials },
+    // Authenticates with a token from an earlier login instead of credentials
+    Resume { token: String },
+    // Answer to the `rehash` of a challenge-response login on the same connection, refused otherwise.
+    // Replaces the stored credentials and revokes every other session of the user
+    UpdateCredentials { credentials: Credentials },
+    // The sender is the authenticated user of the connection
+    Chat { room: String, body: String },
+    FetchHistory { room: String, before: HistoryCursor, limit: u32 },
+    CreateRoom { name: String },
+    JoinRoom { name: String },
+    LeaveRoom { name: String },
+    ListRooms,
+    Direct { to: String, body: String },
+    ListSessions,
+    RevokeSession { id: String },
+    // Revokes the session this connection logged in with, answered with `SessionRevoked`
+    Logout,
+}
+
+// Frames sent by the server, encoded the same way as `ClientFrame`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerFrame {
+    Welcome { version: u32, capabilities: Vec<String> },
+    Incompatible { version: u32, min_version: u32, message: String },
+    Registered { username: String, session: SessionToken },
+    // `nonce` is the client nonce with the server nonce appended
+    LoginChallenge { nonce: String, salt: String, params: KdfParams },
+    // `signature` is HMAC(ServerKey, auth message), base64, and proves the server knows the credentials.
+    // `rehash` asks the client to derive new credentials with stronger parameters
+    LoggedIn {
+        username: String,
+        signature: String,
+        session: SessionToken,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rehash: Option<KdfParams>,
+    },
+    Resumed { username: String },
+    // Answer to `LegacyLogin`, the account uses challenge-response login from now on
+    Migrated { username: String, session: SessionToken },
+    CredentialsUpdated,
+    Chat(ChatMessage),
+    History { room: String, messages: Vec<ChatMessage> },
+    HistoryPage { room: String, messages: Vec<ChatMessage>, has_more: bool },
+    RoomCreated { name: String },
+    RoomJoined { name: String },
+    RoomLeft { name: String },
+    RoomList { rooms: Vec<RoomInfo> },
+    Direct(DirectMessage),
+    SessionList { sessions: Vec<SessionInfo> },
+    SessionRevoked { id: String },
+    // Sent to every connection before the server goes down, reconnecting sooner than
+    // `reconnect_after_ms` will most likely fail
+    ShuttingDown { message: String, reconnect_after_ms: u64 },
+    Error { code: ErrorCode, message: String },
+}
+
+impl ClientFrame {
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("Client frames are always serializable")
+    }
+
+    pub fn decode(text: &str) -> Result<Self, DecodeError> {
+        serde_json::from_str(text)
+    }
+}
+
+impl ServerFrame {
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("Server frames are always serializable")
+    }
+
+    pub fn decode(text: &str) -> Result<Self, DecodeError> {
+        serde_json::from_str(text)
+    }
+
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self::Error { code, message: message.into() }
+    }
+}
@@ -0,0 +1,26 @@
+mod frame;
+pub mod scram;
+
+pub use frame::{ChatMessage, ClientFrame, DecodeError, DirectMessage, ErrorCode, HistoryCursor, RoomInfo, ServerFrame, SessionInfo, SessionToken};
+
+// Bumped whenever a frame changes shape in a way older peers can't read
+pub const PROTOCOL_VERSION: u32 = 12;
+// Oldest peer version this build can still talk to
+pub const MIN_PROTOCOL_VERSION: u32 = 12;
+
+// Optional features announced in the handshake
+pub mod features {
+    pub const CHAT: &str = "chat";
+    pub const HISTORY: &str = "history";
+    pub const HISTORY_PAGES: &str = "history_pages";
+    pub const ROOMS: &str = "rooms";
+    pub const DIRECT_MESSAGES: &str = "direct_messages";
+    pub const SESSIONS: &str = "sessions";
+}
+
+// Room every client joins after logging in
+pub const DEFAULT_ROOM: &str = "general";
+
+pub fn is_supported_version(version: u32) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
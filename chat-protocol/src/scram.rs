@@ -0,0 +1,225 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Salted challenge-response login modelled on SCRAM (RFC 5802), with Argon2id instead of PBKDF2.
+// The client derives SaltedPassword = Argon2id(password, salt), ClientKey = HMAC(SaltedPassword, "Client Key"),
+// StoredKey = SHA-256(ClientKey) and ServerKey = HMAC(SaltedPassword, "Server Key").
+// The server only ever stores StoredKey and ServerKey, so neither the password nor anything replayable crosses the wire.
+
+pub const CLIENT_KEY_LABEL: &[u8] = b"Client Key";
+pub const SERVER_KEY_LABEL: &[u8] = b"Server Key";
+
+// Length of the derived keys, every key and signature is a SHA-256 sized value
+pub const KEY_LEN: usize = 32;
+pub const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 18;
+
+// Argon2id cost parameters the SaltedPassword was derived with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    // What new credentials are derived with, raising these makes the server ask for a rehash on login
+    pub const RECOMMENDED: KdfParams = KdfParams {
+        memory_kib: 19 * 1024,
+        iterations: 2,
+        parallelism: 1,
+    };
+
+    // The most a peer may ask for, the other side could otherwise make us run out of memory or time
+    pub const MAXIMUM: KdfParams = KdfParams {
+        memory_kib: 256 * 1024,
+        iterations: 16,
+        parallelism: 8,
+    };
+
+    // True when every cost is at least the one in `other`
+    pub fn at_least(&self, other: &KdfParams) -> bool {
+        self.memory_kib >= other.memory_kib && self.iterations >= other.iterations && self.parallelism >= other.parallelism
+    }
+
+    // True when no cost is above the one in `other`
+    pub fn at_most(&self, other: &KdfParams) -> bool {
+        self.memory_kib <= other.memory_kib && self.iterations <= other.iterations && self.parallelism <= other.parallelism
+    }
+}
+
+// What the server stores for an account, binary values are standard base64
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub salt: String,
+    pub params: KdfParams,
+    pub stored_key: String,
+    pub server_key: String,
+}
+
+// Both signatures are computed over this, `nonce` is the client nonce followed by the server nonce
+pub fn auth_message(username: &str, nonce: &str, salt: &str) -> String {
+    format!("n={username},r={nonce},s={salt}")
+}
+
+// Fresh random nonce, the client and the server each contribute one per login
+pub fn nonce() -> String {
+    STANDARD.encode(rand::random::<[u8; NONCE_LEN]>())
+}
+
+// Derives everything the server stores for an account with a new random salt,
+// the password itself never leaves the client
+pub fn derive_credentials(password: &str, params: KdfParams) -> Result<Credentials, String> {
+    let salt = STANDARD.encode(rand::random::<[u8; SALT_LEN]>());
+    let keys = Keys::derive(password, &salt, params)?;
+    Ok(Credentials {
+        salt,
+        params,
+        stored_key: STANDARD.encode(keys.stored_key()),
+        server_key: STANDARD.encode(&keys.server_key),
+    })
+}
+
+// Client answer to a login challenge, `server_signature` is what the server has to send back
+pub struct LoginProof {
+    pub proof: String,
+    pub server_signature: String,
+}
+
+pub fn login_proof(username: &str, password: &str, client_nonce: &str, nonce: &str, salt: &str, params: KdfParams) -> Result<LoginProof, String> {
+    // A server that doesn't extend our nonce could be replaying an old challenge
+    if !nonce.starts_with(client_nonce) || nonce.len() == client_nonce.len() {
+        return Err("Server sent an invalid login challenge".to_string());
+    }
+
+    let keys = Keys::derive(password, salt, params)?;
+    let auth_message = auth_message(username, nonce, salt);
+    let client_signature = hmac(&keys.stored_key(), auth_message.as_bytes());
+
+    Ok(LoginProof {
+        proof: STANDARD.encode(xor(&keys.client_key, &client_signature)),
+        server_signature: STANDARD.encode(hmac(&keys.server_key, auth_message.as_bytes())),
+    })
+}
+
+// Server side check of a client proof against the stored keys,
+// returns the base64 server signature if the proof is valid
+pub fn verify_proof(stored_key: &[u8], server_key: &[u8], auth_message: &str, proof: &str) -> Option<String> {
+    let proof = decode_key(proof)?;
+    let client_signature = hmac(stored_key, auth_message.as_bytes());
+    let client_key = xor(&proof, &client_signature);
+    if !bool::from(Sha256::digest(&client_key).as_slice().ct_eq(stored_key)) {
+        return None;
+    }
+
+    Some(STANDARD.encode(hmac(server_key, auth_message.as_bytes())))
+}
+
+// A base64 key of the expected length
+pub fn decode_key(key: &str) -> Option<Vec<u8>> {
+    STANDARD.decode(key).ok().filter(|key| key.len() == KEY_LEN)
+}
+
+pub fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b.iter()).map(|(a, b)| a ^ b).collect()
+}
+
+struct Keys {
+    client_key: Vec<u8>,
+    server_key: Vec<u8>,
+}
+
+impl Keys {
+    fn derive(password: &str, salt: &str, params: KdfParams) -> Result<Self, String> {
+        if !params.at_most(&KdfParams::MAXIMUM) {
+            return Err("Key derivation parameters are too expensive".to_string());
+        }
+        let salt = STANDARD.decode(salt).map_err(|_| "Salt is not valid base64")?;
+        let argon2_params = Params::new(params.memory_kib, params.iterations, params.parallelism, None)
+            .map_err(|err| format!("Invalid key derivation parameters => {err}"))?;
+        let mut salted_password = [0u8; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, argon2_params)
+            .hash_password_into(password.as_bytes(), &salt, &mut salted_password)
+            .map_err(|err| format!("Failed to derive keys => {err}"))?;
+
+        Ok(Self {
+            client_key: hmac(&salted_password, CLIENT_KEY_LABEL),
+            server_key: hmac(&salted_password, SERVER_KEY_LABEL),
+        })
+    }
+
+    fn stored_key(&self) -> Vec<u8> {
+        Sha256::digest(&self.client_key).to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap enough to keep the tests fast, the exchange works the same with any cost
+    const PARAMS: KdfParams = KdfParams { memory_kib: 1024, iterations: 1, parallelism: 1 };
+
+    // Runs a whole login against `credentials`, returns the server signature if the server accepts it
+    fn log_in(credentials: &Credentials, password: &str, server_nonce: &str) -> Option<(String, LoginProof)> {
+        let client_nonce = nonce();
+        let nonce = format!("{client_nonce}{server_nonce}");
+        let proof = login_proof("alice", password, &client_nonce, &nonce, &credentials.salt, credentials.params).unwrap();
+
+        let stored_key = decode_key(&credentials.stored_key).unwrap();
+        let server_key = decode_key(&credentials.server_key).unwrap();
+        let signature = verify_proof(&stored_key, &server_key, &auth_message("alice", &nonce, &credentials.salt), &proof.proof)?;
+        Some((signature, proof))
+    }
+
+    #[test]
+    fn login_round_trip() {
+        let credentials = derive_credentials("correct horse", PARAMS).unwrap();
+        let (signature, proof) = log_in(&credentials, "correct horse", &nonce()).unwrap();
+        assert_eq!(signature, proof.server_signature);
+    }
+
+    #[test]
+    fn wrong_password_is_rejected() {
+        let credentials = derive_credentials("correct horse", PARAMS).unwrap();
+        assert!(log_in(&credentials, "battery staple", &nonce()).is_none());
+    }
+
+    #[test]
+    fn proof_is_bound_to_the_nonce() {
+        let credentials = derive_credentials("correct horse", PARAMS).unwrap();
+        let stored_key = decode_key(&credentials.stored_key).unwrap();
+        let server_key = decode_key(&credentials.server_key).unwrap();
+        let client_nonce = nonce();
+        let nonce = format!("{client_nonce}{}", nonce());
+        let proof = login_proof("alice", "correct horse", &client_nonce, &nonce, &credentials.salt, PARAMS).unwrap();
+
+        let tampered = format!("{nonce}x");
+        assert!(verify_proof(&stored_key, &server_key, &auth_message("alice", &tampered, &credentials.salt), &proof.proof).is_none());
+    }
+
+    #[test]
+    fn challenge_must_extend_the_client_nonce() {
+        let client_nonce = nonce();
+        assert!(login_proof("alice", "correct horse", &client_nonce, &client_nonce, "c2FsdHNhbHRzYWx0c2FsdA==", PARAMS).is_err());
+        assert!(login_proof("alice", "correct horse", &client_nonce, &nonce(), "c2FsdHNhbHRzYWx0c2FsdA==", PARAMS).is_err());
+    }
+
+    #[test]
+    fn expensive_params_are_refused() {
+        let params = KdfParams { memory_kib: u32::MAX, ..KdfParams::RECOMMENDED };
+        assert!(derive_credentials("correct horse", params).is_err());
+    }
+}
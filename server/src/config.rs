@@ -0,0 +1,268 @@
+use std::{fmt, fs, io, net::SocketAddr, path::{Path, PathBuf}, time::Duration};
+
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+// Used when neither `--config` nor `CHAT_CONFIG` point somewhere else
+const DEFAULT_CONFIG_PATH: &str = "chat-server.toml";
+
+#[derive(Parser)]
+#[command(version, about = "WebSocket chat server")]
+pub struct Args {
+    /// Path to the TOML config file
+    #[arg(short, long, env = "CHAT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address the WebSocket server listens on
+    #[arg(long, env = "CHAT_BIND")]
+    pub bind: Option<String>,
+
+    /// Storage backend
+    #[arg(long, env = "CHAT_STORAGE", value_enum)]
+    pub storage: Option<StorageKind>,
+
+    /// SQLite database file, created if missing
+    #[arg(long, env = "CHAT_SQLITE_PATH")]
+    pub sqlite_path: Option<PathBuf>,
+
+    /// How often to try reaching the database at startup before giving up
+    #[arg(long, env = "CHAT_STORAGE_CONNECT_ATTEMPTS")]
+    pub storage_connect_attempts: Option<u32>,
+
+    /// MongoDB connection string
+    #[arg(long, env = "CHAT_MONGO_URI")]
+    pub mongo_uri: Option<String>,
+
+    /// MongoDB database holding the chat collections
+    #[arg(long, env = "CHAT_DB_NAME")]
+    pub db_name: Option<String>,
+
+    /// PEM certificate chain, enables wss:// together with --tls-key
+    #[arg(long, env = "CHAT_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "CHAT_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// How many days a login can be resumed with its session token
+    #[arg(long, env = "CHAT_SESSION_TTL_DAYS")]
+    pub session_ttl_days: Option<u64>,
+
+    /// Seconds between pings to every connection
+    #[arg(long, env = "CHAT_PING_INTERVAL_SECS")]
+    pub ping_interval_secs: Option<u64>,
+
+    /// Seconds a client has to answer a ping before it is considered dead
+    #[arg(long, env = "CHAT_PONG_TIMEOUT_SECS")]
+    pub pong_timeout_secs: Option<u64>,
+
+    /// Seconds without any frame from the client before it is disconnected
+    #[arg(long, env = "CHAT_IDLE_TIMEOUT_SECS")]
+    pub idle_timeout_secs: Option<u64>,
+
+    /// Seconds connections get to wind down after SIGINT or SIGTERM
+    #[arg(long, env = "CHAT_SHUTDOWN_TIMEOUT_SECS")]
+    pub shutdown_timeout_secs: Option<u64>,
+
+    /// Print the effective configuration and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: String,
+    pub storage: StorageKind,
+    pub sqlite_path: PathBuf,
+    // Attempts are spaced with exponential backoff
+    pub storage_connect_attempts: u32,
+    pub mongo_uri: String,
+    pub db_name: String,
+    pub session_ttl_days: u64,
+    // Both set serves wss://, neither plain ws://
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub ping_interval_secs: u64,
+    pub pong_timeout_secs: u64,
+    pub idle_timeout_secs: u64,
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    Mongo,
+    Sqlite,
+    // Keeps everything in process memory, for development and CI
+    Memory,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:8080".to_string(),
+            storage: StorageKind::Mongo,
+            sqlite_path: PathBuf::from("chat.db"),
+            storage_connect_attempts: 6,
+            mongo_uri: "mongodb://localhost:27017".to_string(),
+            db_name: "chat".to_string(),
+            session_ttl_days: 30,
+            tls_cert: None,
+            tls_key: None,
+            ping_interval_secs: 30,
+            pong_timeout_secs: 10,
+            idle_timeout_secs: 900,
+            shutdown_timeout_secs: 10,
+        }
+    }
+}
+
+impl Config {
+    // Defaults, then the config file, then environment variables and flags
+    pub fn load(args: &Args) -> Result<Self, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?,
+            None => Self::default(),
+        };
+
+        if let Some(bind) = &args.bind {
+            config.bind = bind.clone();
+        }
+        if let Some(storage) = args.storage {
+            config.storage = storage;
+        }
+        if let Some(sqlite_path) = &args.sqlite_path {
+            config.sqlite_path = sqlite_path.clone();
+        }
+        if let Some(storage_connect_attempts) = args.storage_connect_attempts {
+            config.storage_connect_attempts = storage_connect_attempts;
+        }
+        if let Some(mongo_uri) = &args.mongo_uri {
+            config.mongo_uri = mongo_uri.clone();
+        }
+        if let Some(db_name) = &args.db_name {
+            config.db_name = db_name.clone();
+        }
+        if let Some(session_ttl_days) = args.session_ttl_days {
+            config.session_ttl_days = session_ttl_days;
+        }
+        if let Some(ping_interval_secs) = args.ping_interval_secs {
+            config.ping_interval_secs = ping_interval_secs;
+        }
+        if let Some(pong_timeout_secs) = args.pong_timeout_secs {
+            config.pong_timeout_secs = pong_timeout_secs;
+        }
+        if let Some(idle_timeout_secs) = args.idle_timeout_secs {
+            config.idle_timeout_secs = idle_timeout_secs;
+        }
+        if let Some(shutdown_timeout_secs) = args.shutdown_timeout_secs {
+            config.shutdown_timeout_secs = shutdown_timeout_secs;
+        }
+        if let Some(tls_cert) = &args.tls_cert {
+            config.tls_cert = Some(tls_cert.clone());
+        }
+        if let Some(tls_key) = &args.tls_key {
+            config.tls_key = Some(tls_key.clone());
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_path_buf(), err))?;
+        toml::from_str(&content).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.bind.parse::<SocketAddr>().is_err() {
+            return Err(ConfigError::Invalid(format!("bind must be an ip:port address, got '{}'", self.bind)));
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(ConfigError::Invalid("tls_cert and tls_key must be set together".to_string()));
+        }
+        if self.ping_interval_secs == 0 || self.pong_timeout_secs == 0 || self.idle_timeout_secs == 0 {
+            return Err(ConfigError::Invalid("ping_interval_secs, pong_timeout_secs and idle_timeout_secs must be at least 1".to_string()));
+        }
+        if self.storage_connect_attempts == 0 {
+            return Err(ConfigError::Invalid("storage_connect_attempts must be at least 1".to_string()));
+        }
+        if self.session_ttl_days == 0 {
+            return Err(ConfigError::Invalid("session_ttl_days must be at least 1".to_string()));
+        }
+        if self.storage == StorageKind::Sqlite && self.sqlite_path.as_os_str().is_empty() {
+            return Err(ConfigError::Invalid("sqlite_path must not be empty".to_string()));
+        }
+        if self.storage != StorageKind::Mongo {
+            return Ok(());
+        }
+        if !self.mongo_uri.starts_with("mongodb://") && !self.mongo_uri.starts_with("mongodb+srv://") {
+            return Err(ConfigError::Invalid("mongo_uri must start with mongodb:// or mongodb+srv://".to_string()));
+        }
+        // Characters MongoDB doesn't allow in database names
+        if self.db_name.is_empty() || self.db_name.contains(['/', '\\', '.', ' ', '"', '$', '*', '<', '>', ':', '|', '?']) {
+            return Err(ConfigError::Invalid(format!("db_name '{}' is not a valid MongoDB database name", self.db_name)));
+        }
+        Ok(())
+    }
+
+    pub fn session_ttl(&self) -> Duration {
+        Duration::from_secs(self.session_ttl_days.saturating_mul(24 * 60 * 60))
+    }
+
+    pub fn ping_interval(&self) -> Duration {
+        Duration::from_secs(self.ping_interval_secs)
+    }
+
+    pub fn pong_timeout(&self) -> Duration {
+        Duration::from_secs(self.pong_timeout_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs)
+    }
+
+    // TOML dump of the config with the MongoDB password masked
+    pub fn to_redacted_toml(&self) -> String {
+        let mut config = self.clone();
+        config.mongo_uri = redact_uri(&self.mongo_uri);
+        toml::to_string_pretty(&config).expect("Config is always serializable")
+    }
+}
+
+fn redact_uri(uri: &str) -> String {
+    let Some((scheme, rest)) = uri.split_once("://") else {
+        return uri.to_string();
+    };
+    match rest.split_once('@') {
+        Some((credentials, host)) => match credentials.split_once(':') {
+            Some((user, _)) => format!("{scheme}://{user}:****@{host}"),
+            None => uri.to_string(),
+        },
+        None => uri.to_string(),
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "Failed to read config file {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "Failed to parse config file {}: {}", path.display(), err),
+            ConfigError::Invalid(message) => write!(f, "Invalid configuration: {}", message),
+        }
+    }
+}
@@ -1,46 +1,43 @@
+use std::time::Duration;
+
 use mongodb::{bson, error::Result, options::ClientOptions, Client};
 
+const SERVER_SELECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Clone)]
 pub struct Database {
     client: Option<Client>,
     connection: String,
+    database_name: String,
 }
 
 impl Database {
     
-    pub fn new(connection: String) -> Self {
+    pub fn new(connection: String, database_name: String) -> Self {
         Self {
             client: None,
             connection,
+            database_name,
         }
     }
 
     pub async fn connect(&self) -> Result<Self> {
-        let client_options = ClientOptions::parse(&self.connection).await.expect("Failed to parse client options");
-        let client = Client::with_options(client_options).expect("Failed to create client");
+        let mut client_options = ClientOptions::parse(&self.connection).await?;
+        // The driver waits 30 seconds for a reachable server by default, too long to keep a client waiting
+        client_options.server_selection_timeout = Some(SERVER_SELECTION_TIMEOUT);
+        let client = Client::with_options(client_options)?;
         Ok(Self {
             client: Some(client),
             connection: self.connection.clone(),
+            database_name: self.database_name.clone(),
         })
     }
 
-    #[allow(dead_code)]
-    pub fn get_database(&self, database_name: &str) -> Option<mongodb::Database> {
-        match &self.client {
-            Some(client) => {
-                Some(client.database(database_name))
-            },
-            None => None,
-        }
+    pub fn get_database(&self) -> Option<mongodb::Database> {
+        self.client.as_ref().map(|client| client.database(&self.database_name))
     }
 
-    pub fn get_collection(&self, database_name: &str, collection_nanme: &str) -> Option<mongodb::Collection<bson::Document>> {
-        match &self.client {
-            Some(client) => {
-                Some(client.database(database_name).collection(collection_nanme))
-            },
-            None => None,
-        }
-        
+    pub fn get_collection(&self, collection_nanme: &str) -> Option<mongodb::Collection<bson::Document>> {
+        self.client.as_ref().map(|client| client.database(&self.database_name).collection(collection_nanme))
     }
 }
\ No newline at end of file
@@ -1,70 +1,511 @@
-use futures_util::{SinkExt, StreamExt};
+use std::time::Duration;
+
+use futures_util::{SinkExt, Stream, StreamExt};
+use mongodb::bson::{oid::ObjectId, DateTime};
 use tokio_tungstenite::accept_async;
-use tungstenite::Message;
-use tokio::net::TcpStream;
+use tungstenite::{protocol::{frame::coding::CloseCode, CloseFrame}, Message};
+use tokio::{io::{AsyncRead, AsyncWrite}, sync::{mpsc::{self, UnboundedSender}, oneshot, watch}, time::{sleep_until, timeout, Instant}};
+
+use chat_protocol::{features, scram::{self, auth_message, Credentials, KdfParams}, is_supported_version, ClientFrame, ErrorCode, HistoryCursor, RoomInfo, ServerFrame, DEFAULT_ROOM, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
 
-use crate::user::user::User;
+use crate::{config::Config, error::ServerError, message::{direct::StoredDirectMessage, message::StoredMessage}, name::{invalid_name_message, is_valid_name}, room::room::Room, session::session::{hash_token, StoredSession}, storage::SharedStorage, user::{password, scram::{decoy_salt, StoredCredentials}, user::User}};
 
-use super::database::Database;
+use super::hub::{Hub, SessionId};
 
-pub async fn handle_connection(stream: TcpStream, database: &Database) {
-    let ws_stream = accept_async(stream).await.expect("Failed to accept WebSocket connection");
+const CAPABILITIES: &[&str] = &[features::CHAT, features::HISTORY, features::HISTORY_PAGES, features::ROOMS, features::DIRECT_MESSAGES, features::SESSIONS];
+// How many messages a freshly logged in client gets replayed
+const HISTORY_LIMIT: i64 = 50;
+// Upper bound on the page size a client may ask for
+const MAX_HISTORY_PAGE: u32 = 100;
+// Time a new connection gets to finish the handshake and log in
+pub const LOGIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+// `shutdown` flips to true when the server is going down, the connection then finishes
+// the frame it is handling so nothing already received is lost, and closes the socket
+pub async fn handle_connection<S>(stream: S, storage: &SharedStorage, hub: &Hub, config: &Config, mut shutdown: watch::Receiver<bool>) -> Result<(), ServerError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // A peer that never sends the upgrade request must not hold on to the connection
+    let Ok(ws_stream) = timeout(LOGIN_TIMEOUT, accept_async(stream)).await else {
+        return Ok(());
+    };
+    let ws_stream = ws_stream?;
 
     let (mut write, mut read) = ws_stream.split();
 
+    // Everything written to this socket goes through the channel, so the hub can reach it too
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Message>();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            if let Err(e) = write.send(message).await {
+                eprintln!("Failed to send message: {}", e);
+                break;
+            }
+        }
+        // Completes the close handshake, or starts one if nobody did yet
+        let _ = write.close().await;
+    });
 
-    match write.send(Message::Text("connection:success".to_string())).await {
-        Ok(_) => {
-            if let Some(Ok(Message::Text(credentials))) = read.next().await {
-                let cloned_credentials = credentials.clone();
-                let split = cloned_credentials.split(":");
-                let cred = split.collect::<Vec<&str>>();
-
-                if cred[0] == "register" {
-                    let user = User::from_credentials(&credentials);
-                    match User::insert_user(database, &user).await {
-                        Ok(_) => {
-                            write.send(Message::Text("register:success".to_string())).await.expect("Failed to send message");
-                        },
-                        Err(err) => {
-                            write.send(Message::Text(format!("Failed to register user => {err}"))).await.expect("Failed to send message");
-                        }
-                    };
-                } else if cred[0] == "login" {
-                    let user = User::from_credentials(&credentials);
-                    match User::select_user(database, &user).await {
-                        Ok(user) => {
-                            match user {
-                                Some(_) => {
-                                    write.send(Message::Text("login:success".to_string())).await.expect("Failed to send message");
-                                },
-                                None => {
-                                    write.send(Message::Text("Invalid credentials".to_string())).await.expect("Failed to send message");
-                                }
-                            }
-                        },
-                        Err(err) => {
-                            write.send(Message::Text(format!("Failed to login user => \n{err}"))).await.expect("Failed to send message");
-                        }
-                    };
+    let session_id = hub.next_session_id();
+
+    let greeting = match timeout(LOGIN_TIMEOUT, read.next()).await {
+        Ok(Some(Ok(Message::Text(hello)))) => negotiate(&hello),
+        _ => return Ok(()),
+    };
+    let accepted = matches!(greeting, ServerFrame::Welcome { .. });
+
+    send_frame(&sender, greeting)?;
+    if !accepted {
+        close(&sender, CloseCode::Policy, "Incompatible protocol version");
+        return Ok(());
+    }
+
+    // Every frame below acts as this user, the client never gets to name the sender itself
+    let authenticated = tokio::select! {
+        authenticated = timeout(LOGIN_TIMEOUT, authenticate(storage, config, &sender, &mut read)) => authenticated,
+        _ = shutdown.changed() => {
+            close(&sender, CloseCode::Away, "Server shutting down");
+            return Ok(());
+        }
+    };
+    let (username, login_session, mut rehash_requested) = match authenticated {
+        Ok(Some(authenticated)) => authenticated,
+        Ok(None) => return Ok(()),
+        Err(_) => {
+            close(&sender, CloseCode::Policy, "Login timed out");
+            return Ok(());
+        }
+    };
+    let mut removed = enter_chat(storage, hub, session_id, &username, &login_session, &sender).await;
+
+    // Only what the client sends on its own counts as activity, pongs answer our pings and would
+    // keep every connection alive
+    let mut last_activity = Instant::now();
+    let mut last_ping = Instant::now();
+    let mut awaiting_pong: Option<Instant> = None;
+
+    loop {
+        let next_ping = match awaiting_pong {
+            Some(sent) => sent + config.pong_timeout(),
+            None => last_ping + config.ping_interval(),
+        };
+        let deadline = next_ping.min(last_activity + config.idle_timeout());
+
+        let frame = tokio::select! {
+            // Checked first, a revoked session must not get another frame handled even if the
+            // peer ignores the close frame and keeps sending
+            biased;
+            _ = &mut removed => break,
+            frame = read.next() => frame,
+            _ = shutdown.changed() => {
+                close(&sender, CloseCode::Away, "Server shutting down");
+                break;
+            }
+            _ = sleep_until(deadline) => {
+                if last_activity.elapsed() >= config.idle_timeout() {
+                    close(&sender, CloseCode::Normal, "Idle timeout");
+                    break;
                 }
+                if awaiting_pong.is_some_and(|sent| sent.elapsed() >= config.pong_timeout()) {
+                    println!("Dropping {}, no pong within {:?}", username, config.pong_timeout());
+                    close(&sender, CloseCode::Away, "Heartbeat timeout");
+                    break;
+                }
+                if awaiting_pong.is_none() && last_ping.elapsed() >= config.ping_interval() {
+                    let _ = sender.send(Message::Ping(Vec::new()));
+                    last_ping = Instant::now();
+                    awaiting_pong = Some(last_ping);
+                }
+                continue;
+            }
+        };
+
+        if let Some(Ok(Message::Text(_) | Message::Ping(_))) = frame {
+            last_activity = Instant::now();
+        }
+        let message = match frame {
+            Some(Ok(Message::Text(message))) => message,
+            Some(Ok(Message::Pong(_))) => {
+                awaiting_pong = None;
+                continue;
+            },
+            // tungstenite answers pings and close frames on its own
+            Some(Ok(Message::Ping(_))) => continue,
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            Some(Ok(_)) => {
+                let _ = send_error(&sender, ServerError::rejected(ErrorCode::BadRequest, "Only text frames are supported"));
+                continue;
+            },
+        };
+
+        let handled = match ClientFrame::decode(&message) {
+            Ok(frame) => handle_frame(storage, hub, session_id, &username, &login_session, &mut rehash_requested, &sender, frame).await,
+            Err(err) => Err(ServerError::malformed(err)),
+        };
+        // A failed request only costs the client an error frame, the connection stays up
+        if let Err(err) = handled {
+            if send_error(&sender, err).is_err() {
+                break;
             }
+        }
+    }
+
+    hub.unregister(session_id);
+    // Waits for the writer to flush everything queued, including the close frame
+    drop(sender);
+    let _ = writer.await;
+    Ok(())
+}
+
+// Handles one frame of a logged in connection. `rehash_requested` is set while the connection
+// may answer the `rehash` of its challenge-response login with new credentials
+#[allow(clippy::too_many_arguments)]
+async fn handle_frame(storage: &SharedStorage, hub: &Hub, session_id: SessionId, username: &str, login_session: &StoredSession, rehash_requested: &mut bool, sender: &UnboundedSender<Message>, frame: ClientFrame) -> Result<(), ServerError> {
+    match frame {
+        ClientFrame::Chat { room, body } => {
+            ensure_member(hub, session_id, &room)?;
+            println!("#{} {} > {}", room, username, body);
+            let message = StoredMessage::new(username.to_string(), body, room);
+            let stored = storage.insert_message(&message).await;
+            hub.broadcast_to_room(&message.room, Message::Text(ServerFrame::Chat(message.to_chat_message()).encode()));
+            // Delivered live either way, but the sender should know it is missing from the history
+            Ok(stored?)
+        },
+        ClientFrame::FetchHistory { room, before, limit } => {
+            ensure_member(hub, session_id, &room)?;
+            send_history_page(storage, sender, room, before, limit).await
+        },
+        ClientFrame::CreateRoom { name } => {
+            if !is_valid_name(&name) {
+                return Err(ServerError::rejected(ErrorCode::BadRequest, invalid_name_message("Room names")));
+            }
+            storage.insert_room(&Room::new(name.clone(), username.to_string())).await?;
+            send_frame(sender, ServerFrame::RoomCreated { name: name.clone() })?;
+            join_room(storage, hub, session_id, sender, name).await
+        },
+        ClientFrame::JoinRoom { name } => {
+            if storage.select_room(&name).await?.is_none() {
+                return Err(ServerError::rejected(ErrorCode::NotFound, format!("Room #{name} does not exist")));
+            }
+            join_room(storage, hub, session_id, sender, name).await
+        },
+        ClientFrame::LeaveRoom { name } => {
+            if !hub.leave(session_id, &name) {
+                return Err(ServerError::rejected(ErrorCode::NotInRoom, format!("You are not in #{name}")));
+            }
+            send_frame(sender, ServerFrame::RoomLeft { name })
+        },
+        ClientFrame::Direct { to, body } => send_direct_message(storage, hub, username, to, body).await,
+        ClientFrame::UpdateCredentials { credentials } => {
+            // A session token alone must never be enough to take over the account
+            if !std::mem::take(rehash_requested) {
+                return Err(ServerError::rejected(ErrorCode::Unauthenticated, "Credentials can only be updated right after a login that asked for it"));
+            }
+            let credentials = StoredCredentials::from_wire(&credentials)
+                .map_err(|err| ServerError::rejected(ErrorCode::BadRequest, format!("Invalid credentials => {err}")))?;
+            storage.update_password(username, &credentials.encode()).await?;
+            send_frame(sender, ServerFrame::CredentialsUpdated)?;
+            revoke_other_sessions(storage, hub, username, login_session).await
+        },
+        ClientFrame::ListSessions => {
+            let sessions = storage.list_sessions(username).await?.iter()
+                .filter(|session| !session.is_expired())
+                .map(|session| session.to_session_info(session.id == login_session.id))
+                .collect();
+            send_frame(sender, ServerFrame::SessionList { sessions })
+        },
+        ClientFrame::RevokeSession { id } => {
+            let no_session = || ServerError::rejected(ErrorCode::NotFound, format!("No session with id {id}"));
+            let object_id = ObjectId::parse_str(&id).map_err(|_| no_session())?;
+            if !storage.delete_session(username, object_id).await? {
+                return Err(no_session());
+            }
+            send_frame(sender, ServerFrame::SessionRevoked { id: id.clone() })?;
+            // Also ends this connection when it revoked its own session
+            hub.close_login_session(&id);
+            Ok(())
+        },
+        ClientFrame::Logout => {
+            let id = login_session.id.to_hex();
+            storage.delete_session(username, login_session.id).await?;
+            send_frame(sender, ServerFrame::SessionRevoked { id: id.clone() })?;
+            hub.close_login_session(&id);
+            Ok(())
+        },
+        ClientFrame::ListRooms => {
+            let rooms = storage.list_rooms().await?.into_iter().map(|room| RoomInfo {
+                online: hub.online_in(&room.name),
+                name: room.name,
+                created_by: room.created_by,
+            }).collect();
+            send_frame(sender, ServerFrame::RoomList { rooms })
+        },
+        _ => Err(ServerError::rejected(ErrorCode::BadRequest, "Expected a chat frame")),
+    }
+}
+
+// Reads frames until a register, login or resume succeeds, anything else is refused. The flag is set
+// when a challenge-response login asked for new credentials. Returns None if the connection ends first
+async fn authenticate<R>(storage: &SharedStorage, config: &Config, sender: &UnboundedSender<Message>, read: &mut R) -> Option<(String, StoredSession, bool)>
+where
+    R: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    while let Some(Ok(Message::Text(text))) = read.next().await {
+        let authenticated = match ClientFrame::decode(&text) {
+            Ok(ClientFrame::Register { username, credentials }) => register(storage, config, sender, username, &credentials).await.map(|(username, session)| (username, session, false)),
+            Ok(ClientFrame::Login { username, nonce }) => login(storage, config, sender, read, username, &nonce).await,
+            Ok(ClientFrame::LegacyLogin { username, password, credentials }) => legacy_login(storage, config, sender, username, password, &credentials).await.map(|(username, session)| (username, session, false)),
+            Ok(ClientFrame::Resume { token }) => resume(storage, sender, &token).await.map(|(username, session)| (username, session, false)),
+            Ok(_) => Err(ServerError::rejected(ErrorCode::Unauthenticated, "You must register, log in or resume a session first")),
+            Err(err) => Err(ServerError::malformed(err)),
+        };
+        match authenticated {
+            Ok(authenticated) => return Some(authenticated),
+            Err(err) => send_error(sender, err).ok()?,
+        }
+    }
+    None
+}
+
+async fn register(storage: &SharedStorage, config: &Config, sender: &UnboundedSender<Message>, username: String, credentials: &Credentials) -> Result<(String, StoredSession), ServerError> {
+    if !is_valid_name(&username) {
+        return Err(ServerError::rejected(ErrorCode::BadRequest, invalid_name_message("Usernames")));
+    }
+    let credentials = StoredCredentials::from_wire(credentials)
+        .map_err(|err| ServerError::rejected(ErrorCode::BadRequest, format!("Invalid credentials => {err}")))?;
+    storage.insert_user(&User::new(username.clone(), credentials.encode())).await?;
+    let (session, token) = issue_session(storage, config, &username).await?;
+    send_frame(sender, ServerFrame::Registered { username: username.clone(), session: session.to_session_token(token) })?;
+    Ok((username, session))
+}
+
+// Runs the challenge-response exchange after the Login frame. On success the client also
+// gets the server signature and, if its credentials are outdated, a request to rehash them.
+// The flag tells whether it got that request
+async fn login<R>(storage: &SharedStorage, config: &Config, sender: &UnboundedSender<Message>, read: &mut R, username: String, client_nonce: &str) -> Result<(String, StoredSession, bool), ServerError>
+where
+    R: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    let credentials = match storage.select_user(&username).await? {
+        Some(user) => Some(StoredCredentials::parse(&user.password).ok_or_else(|| ServerError::rejected(
+            ErrorCode::AccountOutdated,
+            "This account was created before challenge-response login and has to be migrated",
+        ))?),
+        None => None,
+    };
+    // Unknown users get a believable challenge and fail at the proof like a wrong password would
+    let (salt, params) = match &credentials {
+        Some(credentials) => (credentials.salt.clone(), credentials.params),
+        None => (decoy_salt(&username), KdfParams::RECOMMENDED),
+    };
+
+    let nonce = format!("{client_nonce}{}", scram::nonce());
+    send_frame(sender, ServerFrame::LoginChallenge { nonce: nonce.clone(), salt: salt.clone(), params })?;
+
+    let proof = match read.next().await {
+        Some(Ok(Message::Text(text))) => match ClientFrame::decode(&text) {
+            Ok(ClientFrame::LoginProof { proof }) => proof,
+            Ok(_) => return Err(ServerError::rejected(ErrorCode::BadRequest, "Expected a login proof")),
+            Err(err) => return Err(ServerError::malformed(err)),
         },
-        Err(e) => {
-            eprintln!("Failed to send message: {}", e);
+        _ => return Err(ServerError::Disconnected),
+    };
+
+    let auth_message = auth_message(&username, &nonce, &salt);
+    let invalid = || ServerError::rejected(ErrorCode::InvalidCredentials, "Invalid credentials");
+    let credentials = credentials.ok_or_else(invalid)?;
+    let signature = credentials.verify_proof(&auth_message, &proof).ok_or_else(invalid)?;
+    let rehash = credentials.is_outdated().then_some(KdfParams::RECOMMENDED);
+
+    let (session, token) = issue_session(storage, config, &username).await?;
+    send_frame(sender, ServerFrame::LoggedIn { username: username.clone(), signature, session: session.to_session_token(token), rehash })?;
+    Ok((username, session, rehash.is_some()))
+}
+
+// Last password login of an account from before challenge-response login. It only succeeds
+// together with new credentials, so the legacy value can't be used again afterwards
+async fn legacy_login(storage: &SharedStorage, config: &Config, sender: &UnboundedSender<Message>, username: String, digest: String, credentials: &Credentials) -> Result<(String, StoredSession), ServerError> {
+    let credentials = StoredCredentials::from_wire(credentials)
+        .map_err(|err| ServerError::rejected(ErrorCode::BadRequest, format!("Invalid credentials => {err}")))?;
+    let invalid = || ServerError::rejected(ErrorCode::InvalidCredentials, "Invalid credentials");
+    // Migrated accounts only take the challenge-response login
+    let stored = storage.select_user(&username).await?
+        .map(|user| user.password)
+        .filter(|stored| StoredCredentials::parse(stored).is_none())
+        .ok_or_else(invalid)?;
+    // Argon2 is deliberately slow, keep it off the async workers
+    let valid = tokio::task::spawn_blocking(move || password::verify_legacy(&digest, &stored)).await.unwrap_or(false);
+    if !valid {
+        return Err(invalid());
+    }
+
+    storage.update_password(&username, &credentials.encode()).await?;
+    println!("Migrated {} to challenge-response login", username);
+    let (session, token) = issue_session(storage, config, &username).await?;
+    send_frame(sender, ServerFrame::Migrated { username: username.clone(), session: session.to_session_token(token) })?;
+    Ok((username, session))
+}
+
+// Stores a new login session, the plain token is returned for the client only
+async fn issue_session(storage: &SharedStorage, config: &Config, username: &str) -> Result<(StoredSession, String), ServerError> {
+    if let Err(err) = storage.delete_expired_sessions().await {
+        eprintln!("Failed to delete expired sessions: {}", err);
+    }
+    let (session, token) = StoredSession::issue(username.to_string(), config.session_ttl());
+    storage.insert_session(&session).await?;
+    Ok((session, token))
+}
+
+async fn resume(storage: &SharedStorage, sender: &UnboundedSender<Message>, token: &str) -> Result<(String, StoredSession), ServerError> {
+    let session = storage.select_session(&hash_token(token)).await?
+        .ok_or_else(|| ServerError::rejected(ErrorCode::InvalidSession, "Session is unknown or was revoked, please log in again"))?;
+    if session.is_expired() {
+        let _ = storage.delete_session(&session.username, session.id).await;
+        return Err(ServerError::rejected(ErrorCode::InvalidSession, "Session expired, please log in again"));
+    }
+    send_frame(sender, ServerFrame::Resumed { username: session.username.clone() })?;
+    Ok((session.username.clone(), session))
+}
+
+// Called once a connection has logged in, registered or resumed. The receiver resolves when
+// the hub drops the connection, e.g. because its session was revoked
+async fn enter_chat(storage: &SharedStorage, hub: &Hub, session_id: SessionId, username: &str, login_session: &StoredSession, sender: &UnboundedSender<Message>) -> oneshot::Receiver<()> {
+    // History goes out before registering, so live messages can't slip in ahead of it
+    if let Err(err) = send_history(storage, sender, DEFAULT_ROOM).await {
+        let _ = send_error(sender, err);
+    }
+    let removed = hub.register(session_id, username, &login_session.id.to_hex(), sender.clone());
+    hub.join(session_id, DEFAULT_ROOM);
+    deliver_pending_direct_messages(storage, username, sender).await;
+    removed
+}
+
+async fn deliver_pending_direct_messages(storage: &SharedStorage, username: &str, sender: &UnboundedSender<Message>) {
+    let pending = match storage.pending_direct_messages(username).await {
+        Ok(pending) => pending,
+        Err(err) => {
+            eprintln!("Failed to load pending direct messages: {err}");
             return;
         }
     };
+    if pending.is_empty() {
+        return;
+    }
+
+    for message in &pending {
+        let _ = send_frame(sender, ServerFrame::Direct(message.to_direct_message()));
+    }
+    let ids = pending.iter().map(|message| message.id).collect::<Vec<_>>();
+    if let Err(err) = storage.mark_direct_messages_delivered(&ids).await {
+        eprintln!("Failed to mark direct messages as delivered: {err}");
+    }
+}
 
-    while let Some(Ok(Message::Text(message))) = read.next().await {
-        let split = message.split(":");
-        let msg = split.collect::<Vec<&str>>();
-        if msg[0] == "msg" {
-            let username = msg[1];
-            let message = msg[2];
-            let response = format!("msg:{}:{}", username, message);
-            println!("{}", response);
-            write.send(Message::Text(response)).await.expect("Failed to send message");
+async fn send_direct_message(storage: &SharedStorage, hub: &Hub, from: &str, to: String, body: String) -> Result<(), ServerError> {
+    if !storage.user_exists(&to).await? {
+        return Err(ServerError::rejected(ErrorCode::NotFound, format!("User {to} does not exist")));
+    }
+
+    let mut message = StoredDirectMessage::new(from.to_string(), to, body);
+    let frame = Message::Text(ServerFrame::Direct(message.to_direct_message()).encode());
+    // Offline recipients get the message the next time they log in
+    message.delivered = hub.send_to_user(&message.to, frame.clone());
+    let stored = storage.insert_direct_message(&message).await;
+    // Echo to every session of the sender, so all of their clients show the conversation
+    if message.to != message.from {
+        hub.send_to_user(from, frame);
+    }
+    // An offline recipient won't get it if it wasn't stored
+    Ok(stored?)
+}
+
+// After a credential change only the connection that made it stays logged in
+async fn revoke_other_sessions(storage: &SharedStorage, hub: &Hub, username: &str, login_session: &StoredSession) -> Result<(), ServerError> {
+    for session in storage.list_sessions(username).await? {
+        if session.id != login_session.id && storage.delete_session(username, session.id).await? {
+            hub.close_login_session(&session.id.to_hex());
         }
     }
+    Ok(())
+}
+
+fn ensure_member(hub: &Hub, session_id: SessionId, room: &str) -> Result<(), ServerError> {
+    if !hub.is_member(session_id, room) {
+        return Err(ServerError::rejected(ErrorCode::NotInRoom, format!("You are not in #{room}")));
+    }
+    Ok(())
+}
+
+async fn join_room(storage: &SharedStorage, hub: &Hub, session_id: SessionId, sender: &UnboundedSender<Message>, name: String) -> Result<(), ServerError> {
+    if !hub.join(session_id, &name) {
+        return Err(ServerError::rejected(ErrorCode::AlreadyInRoom, format!("You are already in #{name}")));
+    }
+    send_frame(sender, ServerFrame::RoomJoined { name: name.clone() })?;
+    send_history(storage, sender, &name).await
+}
+
+async fn send_history(storage: &SharedStorage, sender: &UnboundedSender<Message>, room: &str) -> Result<(), ServerError> {
+    let messages = storage.latest_messages(room, HISTORY_LIMIT).await?;
+    let messages = messages.iter().map(StoredMessage::to_chat_message).collect();
+    send_frame(sender, ServerFrame::History { room: room.to_string(), messages })
+}
+
+async fn send_history_page(storage: &SharedStorage, sender: &UnboundedSender<Message>, room: String, before: HistoryCursor, limit: u32) -> Result<(), ServerError> {
+    let id = ObjectId::parse_str(&before.id).map_err(|_| ServerError::rejected(ErrorCode::BadRequest, "Invalid history cursor"))?;
+    let limit = limit.clamp(1, MAX_HISTORY_PAGE) as i64;
+
+    // Ask for one extra message to find out whether anything is left after this page
+    let mut messages = storage.messages_before(&room, Some((DateTime::from_millis(before.timestamp), id)), limit + 1).await?;
+    let has_more = messages.len() as i64 > limit;
+    if has_more {
+        messages.remove(0);
+    }
+    let messages = messages.iter().map(StoredMessage::to_chat_message).collect();
+    send_frame(sender, ServerFrame::HistoryPage { room, messages, has_more })
+}
+
+fn negotiate(hello: &str) -> ServerFrame {
+    let incompatible = |message: String| ServerFrame::Incompatible {
+        version: PROTOCOL_VERSION,
+        min_version: MIN_PROTOCOL_VERSION,
+        message,
+    };
+
+    match ClientFrame::decode(hello) {
+        Ok(ClientFrame::Hello { version, features }) if is_supported_version(version) => {
+            println!("Client connected with protocol v{} [{}]", version, features.join(", "));
+            ServerFrame::Welcome {
+                version: PROTOCOL_VERSION,
+                capabilities: CAPABILITIES.iter().map(|capability| capability.to_string()).collect(),
+            }
+        },
+        Ok(ClientFrame::Hello { version, .. }) if version < MIN_PROTOCOL_VERSION => incompatible(format!(
+            "Client protocol v{version} is no longer supported, this server requires v{MIN_PROTOCOL_VERSION} to v{PROTOCOL_VERSION}. Please upgrade your client"
+        )),
+        Ok(ClientFrame::Hello { version, .. }) => incompatible(format!(
+            "Client protocol v{version} is newer than this server (v{PROTOCOL_VERSION}). Please ask the server administrator to upgrade"
+        )),
+        _ => incompatible("Expected a hello frame, your client is probably outdated. Please upgrade your client".to_string()),
+    }
+}
+
+fn close(sender: &UnboundedSender<Message>, code: CloseCode, reason: &'static str) {
+    let _ = sender.send(Message::Close(Some(CloseFrame { code, reason: reason.into() })));
+}
+
+fn send_frame(sender: &UnboundedSender<Message>, frame: ServerFrame) -> Result<(), ServerError> {
+    sender.send(Message::Text(frame.encode())).map_err(|_| ServerError::Disconnected)
+}
+
+// Tells the client what went wrong, details of internal failures only go to the server log.
+// Fails if the connection is gone
+fn send_error(sender: &UnboundedSender<Message>, err: ServerError) -> Result<(), ServerError> {
+    if err.is_internal() {
+        eprintln!("{}", err);
+    }
+    match err.to_frame() {
+        Some(frame) => send_frame(sender, frame),
+        None => Err(err),
+    }
 }
@@ -0,0 +1,159 @@
+use std::{collections::{HashMap, HashSet}, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex, MutexGuard, PoisonError}};
+
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
+use tungstenite::{protocol::{frame::coding::CloseCode, CloseFrame}, Message};
+
+pub type SessionId = usize;
+
+struct Session {
+    sender: UnboundedSender<Message>,
+    username: String,
+    // Id of the stored login session the connection authenticated with
+    login_session: String,
+    rooms: HashSet<String>,
+    // Tells the connection task to stop handling frames, its login session is gone
+    closed: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct Sessions {
+    by_id: HashMap<SessionId, Session>,
+    // Every live session of a user, filled on successful login
+    by_username: HashMap<String, HashSet<SessionId>>,
+}
+
+impl Sessions {
+    fn remove(&mut self, id: SessionId) -> Option<Session> {
+        let session = self.by_id.remove(&id)?;
+        if let Some(ids) = self.by_username.get_mut(&session.username) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.by_username.remove(&session.username);
+            }
+        }
+        Some(session)
+    }
+}
+
+// Registry of every authenticated connection, shared between connection tasks
+#[derive(Clone, Default)]
+pub struct Hub {
+    sessions: Arc<Mutex<Sessions>>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl Hub {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A connection task that panicked while holding the lock must not take every other connection down with it
+    fn lock(&self) -> MutexGuard<'_, Sessions> {
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn next_session_id(&self) -> SessionId {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // The receiver resolves once the connection has to stop, either because its login session was
+    // revoked or because it was dropped from the hub. Frames must not be handled after that
+    pub fn register(&self, id: SessionId, username: &str, login_session: &str, sender: UnboundedSender<Message>) -> oneshot::Receiver<()> {
+        let mut sessions = self.lock();
+        let (closed, receiver) = oneshot::channel();
+        let session = Session {
+            sender,
+            username: username.to_string(),
+            login_session: login_session.to_string(),
+            rooms: HashSet::new(),
+            closed,
+        };
+        sessions.by_id.insert(id, session);
+        sessions.by_username.entry(username.to_string()).or_default().insert(id);
+        receiver
+    }
+
+    pub fn unregister(&self, id: SessionId) {
+        self.lock().remove(id);
+    }
+
+    // Returns false if the session already was in the room
+    pub fn join(&self, id: SessionId, room: &str) -> bool {
+        match self.lock().by_id.get_mut(&id) {
+            Some(session) => session.rooms.insert(room.to_string()),
+            None => false,
+        }
+    }
+
+    // Returns false if the session wasn't in the room
+    pub fn leave(&self, id: SessionId, room: &str) -> bool {
+        match self.lock().by_id.get_mut(&id) {
+            Some(session) => session.rooms.remove(room),
+            None => false,
+        }
+    }
+
+    pub fn is_member(&self, id: SessionId, room: &str) -> bool {
+        self.lock().by_id.get(&id).is_some_and(|session| session.rooms.contains(room))
+    }
+
+    pub fn online_in(&self, room: &str) -> usize {
+        self.lock().by_id.values().filter(|session| session.rooms.contains(room)).count()
+    }
+
+    pub fn broadcast_to_room(&self, room: &str, message: Message) {
+        let mut sessions = self.lock();
+        let closed = sessions.by_id.iter()
+            .filter(|(_, session)| session.rooms.contains(room))
+            .filter(|(_, session)| session.sender.send(message.clone()).is_err())
+            .map(|(id, _)| *id)
+            .collect::<Vec<SessionId>>();
+        // A failed send means the connection task is gone, so drop its entry
+        for id in closed {
+            sessions.remove(id);
+        }
+    }
+
+    // Sends the message to every authenticated connection
+    pub fn broadcast(&self, message: Message) {
+        let sessions = self.lock();
+        for session in sessions.by_id.values() {
+            let _ = session.sender.send(message.clone());
+        }
+    }
+
+    // Returns false if the user has no live session
+    pub fn send_to_user(&self, username: &str, message: Message) -> bool {
+        let mut sessions = self.lock();
+        let ids = sessions.by_username.get(username).cloned().unwrap_or_default();
+        let mut delivered = false;
+        for id in ids {
+            let sent = sessions.by_id.get(&id).is_some_and(|session| session.sender.send(message.clone()).is_ok());
+            if sent {
+                delivered = true;
+            } else {
+                sessions.remove(id);
+            }
+        }
+        delivered
+    }
+
+    // Closes every connection that authenticated with the login session, e.g. after it was revoked
+    pub fn close_login_session(&self, login_session: &str) {
+        let mut sessions = self.lock();
+        let ids = sessions.by_id.iter()
+            .filter(|(_, session)| session.login_session == login_session)
+            .map(|(id, _)| *id)
+            .collect::<Vec<SessionId>>();
+        for id in ids {
+            if let Some(session) = sessions.remove(id) {
+                let _ = session.sender.send(Message::Close(Some(CloseFrame {
+                    code: CloseCode::Policy,
+                    reason: "Session revoked".into(),
+                })));
+                let _ = session.closed.send(());
+            }
+        }
+    }
+}
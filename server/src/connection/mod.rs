@@ -1,2 +1,4 @@
 pub mod database;
-pub mod handle;
\ No newline at end of file
+pub mod handle;
+pub mod hub;
+pub mod tls;
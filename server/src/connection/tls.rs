@@ -0,0 +1,27 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+use tokio_rustls::{rustls::{crypto::ring, ServerConfig}, TlsAcceptor};
+
+// Builds the acceptor for wss:// from PEM files, the certificate file may hold the whole chain
+pub fn load_acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor, String> {
+    let cert_file = File::open(cert_path).map_err(|err| format!("Failed to open {}: {}", cert_path.display(), err))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("Failed to read certificates from {}: {}", cert_path.display(), err))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", cert_path.display()));
+    }
+
+    let key_file = File::open(key_path).map_err(|err| format!("Failed to open {}: {}", key_path.display(), err))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))
+        .map_err(|err| format!("Failed to read private key from {}: {}", key_path.display(), err))?
+        .ok_or_else(|| format!("No private key found in {}", key_path.display()))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| format!("Invalid certificate or key: {}", err))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+
+use chat_protocol::{DecodeError, ErrorCode, ServerFrame};
+
+use crate::storage::StorageError;
+
+// Everything that can go wrong while serving a connection, it only ever ends that connection
+#[derive(Debug)]
+pub enum ServerError {
+    // The client asked for something that can't be done, the message is shown to the user as is
+    Rejected(ErrorCode, String),
+    Storage(StorageError),
+    // Boxed, the tungstenite error is large and every handler returns this type
+    WebSocket(Box<tungstenite::Error>),
+    // The socket is gone, nothing can be sent on this connection anymore
+    Disconnected,
+}
+
+impl ServerError {
+    pub fn rejected(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self::Rejected(code, message.into())
+    }
+
+    pub fn malformed(err: DecodeError) -> Self {
+        Self::rejected(ErrorCode::BadRequest, format!("Malformed frame => {err}"))
+    }
+
+    // Failures of the server itself rather than of the request
+    pub fn is_internal(&self) -> bool {
+        matches!(self, Self::Storage(StorageError::Backend(_)))
+    }
+
+    // The error frame for the client, None if there is nobody left to tell.
+    // Backend details stay in the server log
+    pub fn to_frame(&self) -> Option<ServerFrame> {
+        match self {
+            Self::Rejected(code, message) => Some(ServerFrame::error(*code, message.clone())),
+            Self::Storage(StorageError::UsernameTaken) => Some(ServerFrame::error(ErrorCode::UsernameTaken, "Username already exists")),
+            Self::Storage(StorageError::RoomExists) => Some(ServerFrame::error(ErrorCode::RoomExists, "Room already exists")),
+            Self::Storage(StorageError::Unavailable(_)) => Some(ServerFrame::error(ErrorCode::ServiceDegraded, "The server can't reach its database right now, please try again in a moment")),
+            Self::Storage(StorageError::Backend(_)) => Some(ServerFrame::error(ErrorCode::Internal, "Something went wrong on the server, please try again later")),
+            Self::WebSocket(_) | Self::Disconnected => None,
+        }
+    }
+}
+
+impl From<StorageError> for ServerError {
+    fn from(err: StorageError) -> Self {
+        Self::Storage(err)
+    }
+}
+
+impl From<tungstenite::Error> for ServerError {
+    fn from(err: tungstenite::Error) -> Self {
+        Self::WebSocket(Box::new(err))
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Rejected(code, message) => write!(f, "{:?}: {}", code, message),
+            ServerError::Storage(err) => write!(f, "Storage error: {}", err),
+            ServerError::WebSocket(err) => write!(f, "WebSocket error: {}", err),
+            ServerError::Disconnected => write!(f, "Connection closed"),
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoredDirectMessage {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub from: String,
+    pub to: String,
+    pub body: String,
+    pub timestamp: DateTime,
+    // False while the recipient has been offline since the message was sent
+    pub delivered: bool,
+}
+
+impl StoredDirectMessage {
+    pub fn new(from: String, to: String, body: String) -> Self {
+        Self {
+            id: ObjectId::new(),
+            from,
+            to,
+            body,
+            timestamp: DateTime::now(),
+            delivered: false,
+        }
+    }
+
+    pub fn to_direct_message(&self) -> chat_protocol::DirectMessage {
+        chat_protocol::DirectMessage {
+            id: self.id.to_hex(),
+            from: self.from.clone(),
+            to: self.to.clone(),
+            body: self.body.clone(),
+            timestamp: self.timestamp.timestamp_millis(),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoredMessage {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub sender: String,
+    pub body: String,
+    pub timestamp: DateTime,
+    pub room: String,
+}
+
+impl StoredMessage {
+    pub fn new(sender: String, body: String, room: String) -> Self {
+        Self {
+            id: ObjectId::new(),
+            sender,
+            body,
+            timestamp: DateTime::now(),
+            room,
+        }
+    }
+
+    pub fn to_chat_message(&self) -> chat_protocol::ChatMessage {
+        chat_protocol::ChatMessage {
+            id: self.id.to_hex(),
+            sender: self.sender.clone(),
+            body: self.body.clone(),
+            timestamp: self.timestamp.timestamp_millis(),
+            room: self.room.clone(),
+        }
+    }
+}
@@ -0,0 +1,15 @@
+// Usernames and room names follow the same rule, so either is always one word for
+// `/join <room>`, `/msg <user>` and `@<user>` conversations
+
+const MAX_NAME_LENGTH: usize = 32;
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// What a client is told when `is_valid_name` fails, `what` is e.g. "Usernames"
+pub fn invalid_name_message(what: &str) -> String {
+    format!("{what} may only contain letters, digits, '-' and '_' (up to {MAX_NAME_LENGTH} characters)")
+}
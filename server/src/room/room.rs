@@ -0,0 +1,20 @@
+use mongodb::bson::DateTime;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Room {
+    pub name: String,
+    pub created_by: String,
+    pub created_at: DateTime,
+}
+
+impl Room {
+    pub fn new(name: String, created_by: String) -> Self {
+        Self {
+            name,
+            created_by,
+            created_at: DateTime::now(),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const TOKEN_LEN: usize = 32;
+
+// A login that can be resumed with its token. Only the SHA-256 of the token is stored,
+// so a database leak doesn't hand out working sessions
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoredSession {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub username: String,
+    pub token_hash: String,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
+}
+
+impl StoredSession {
+    // Returns the session together with the plain token, which is only ever handed to the client
+    pub fn issue(username: String, ttl: Duration) -> (Self, String) {
+        let token = URL_SAFE_NO_PAD.encode(rand::random::<[u8; TOKEN_LEN]>());
+        let created_at = DateTime::now();
+        let session = Self {
+            id: ObjectId::new(),
+            username,
+            token_hash: hash_token(&token),
+            created_at,
+            expires_at: DateTime::from_millis(created_at.timestamp_millis().saturating_add(ttl.as_millis() as i64)),
+        };
+        (session, token)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= DateTime::now()
+    }
+
+    pub fn to_session_token(&self, token: String) -> chat_protocol::SessionToken {
+        chat_protocol::SessionToken {
+            token,
+            expires_at: self.expires_at.timestamp_millis(),
+        }
+    }
+
+    pub fn to_session_info(&self, current: bool) -> chat_protocol::SessionInfo {
+        chat_protocol::SessionInfo {
+            id: self.id.to_hex(),
+            created_at: self.created_at.timestamp_millis(),
+            expires_at: self.expires_at.timestamp_millis(),
+            current,
+        }
+    }
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,166 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Mutex, MutexGuard, PoisonError};
+
+use async_trait::async_trait;
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use crate::{message::{direct::StoredDirectMessage, message::StoredMessage}, room::room::Room, session::session::StoredSession, user::user::User};
+
+use super::{Storage, StorageError};
+
+#[derive(Default)]
+struct Data {
+    users: Vec<User>,
+    messages: Vec<StoredMessage>,
+    direct_messages: Vec<StoredDirectMessage>,
+    rooms: Vec<Room>,
+    sessions: Vec<StoredSession>,
+}
+
+// Keeps everything in process memory, nothing survives a restart.
+// Tests switch it unavailable to see how the server behaves during a database outage
+pub struct MemoryStorage {
+    data: Mutex<Data>,
+    available: AtomicBool,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self {
+            data: Mutex::new(Data::default()),
+            available: AtomicBool::new(true),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set_available(&self, available: bool) {
+        self.available.store(available, Ordering::Relaxed);
+    }
+
+    fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    fn data(&self) -> Result<MutexGuard<'_, Data>, StorageError> {
+        if !self.is_available() {
+            return Err(StorageError::Unavailable("In-memory storage was switched off".to_string()));
+        }
+        Ok(self.data.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn ping(&self) -> Result<(), StorageError> {
+        self.data().map(|_| ())
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), StorageError> {
+        let mut data = self.data()?;
+        if data.users.iter().any(|existing| existing.username == user.username) {
+            return Err(StorageError::UsernameTaken);
+        }
+        data.users.push(user.clone());
+        Ok(())
+    }
+
+    async fn select_user(&self, username: &str) -> Result<Option<User>, StorageError> {
+        let data = self.data()?;
+        Ok(data.users.iter().find(|user| user.username == username).cloned())
+    }
+
+    async fn update_password(&self, username: &str, password: &str) -> Result<(), StorageError> {
+        let mut data = self.data()?;
+        if let Some(user) = data.users.iter_mut().find(|user| user.username == username) {
+            user.password = password.to_string();
+        }
+        Ok(())
+    }
+
+    async fn user_exists(&self, username: &str) -> Result<bool, StorageError> {
+        Ok(self.data()?.users.iter().any(|user| user.username == username))
+    }
+
+    async fn insert_message(&self, message: &StoredMessage) -> Result<(), StorageError> {
+        self.data()?.messages.push(message.clone());
+        Ok(())
+    }
+
+    async fn messages_before(&self, room: &str, before: Option<(DateTime, ObjectId)>, limit: i64) -> Result<Vec<StoredMessage>, StorageError> {
+        let data = self.data()?;
+        let mut messages = data.messages.iter()
+            .filter(|message| message.room == room)
+            .filter(|message| before.is_none_or(|cursor| (message.timestamp, message.id) < cursor))
+            .cloned()
+            .collect::<Vec<StoredMessage>>();
+        messages.sort_by_key(|message| (message.timestamp, message.id));
+
+        let skip = messages.len().saturating_sub(limit.max(0) as usize);
+        Ok(messages.split_off(skip))
+    }
+
+    async fn insert_direct_message(&self, message: &StoredDirectMessage) -> Result<(), StorageError> {
+        self.data()?.direct_messages.push(message.clone());
+        Ok(())
+    }
+
+    async fn pending_direct_messages(&self, username: &str) -> Result<Vec<StoredDirectMessage>, StorageError> {
+        let data = self.data()?;
+        Ok(data.direct_messages.iter().filter(|message| message.to == username && !message.delivered).cloned().collect())
+    }
+
+    async fn mark_direct_messages_delivered(&self, ids: &[ObjectId]) -> Result<(), StorageError> {
+        let mut data = self.data()?;
+        for message in data.direct_messages.iter_mut().filter(|message| ids.contains(&message.id)) {
+            message.delivered = true;
+        }
+        Ok(())
+    }
+
+    async fn insert_room(&self, room: &Room) -> Result<(), StorageError> {
+        let mut data = self.data()?;
+        if data.rooms.iter().any(|existing| existing.name == room.name) {
+            return Err(StorageError::RoomExists);
+        }
+        data.rooms.push(room.clone());
+        Ok(())
+    }
+
+    async fn select_room(&self, name: &str) -> Result<Option<Room>, StorageError> {
+        Ok(self.data()?.rooms.iter().find(|room| room.name == name).cloned())
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<Room>, StorageError> {
+        let mut rooms = self.data()?.rooms.clone();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(rooms)
+    }
+
+    async fn insert_session(&self, session: &StoredSession) -> Result<(), StorageError> {
+        self.data()?.sessions.push(session.clone());
+        Ok(())
+    }
+
+    async fn select_session(&self, token_hash: &str) -> Result<Option<StoredSession>, StorageError> {
+        let data = self.data()?;
+        Ok(data.sessions.iter().find(|session| session.token_hash == token_hash).cloned())
+    }
+
+    async fn list_sessions(&self, username: &str) -> Result<Vec<StoredSession>, StorageError> {
+        let data = self.data()?;
+        let mut sessions = data.sessions.iter().filter(|session| session.username == username).cloned().collect::<Vec<StoredSession>>();
+        sessions.sort_by_key(|session| session.created_at);
+        Ok(sessions)
+    }
+
+    async fn delete_session(&self, username: &str, id: ObjectId) -> Result<bool, StorageError> {
+        let mut data = self.data()?;
+        let before = data.sessions.len();
+        data.sessions.retain(|session| !(session.id == id && session.username == username));
+        Ok(data.sessions.len() != before)
+    }
+
+    async fn delete_expired_sessions(&self) -> Result<(), StorageError> {
+        self.data()?.sessions.retain(|session| !session.is_expired());
+        Ok(())
+    }
+}
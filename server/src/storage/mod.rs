@@ -0,0 +1,82 @@
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use crate::{message::{direct::StoredDirectMessage, message::StoredMessage}, room::room::Room, session::session::StoredSession, user::user::User};
+
+pub mod memory;
+pub mod mongo;
+pub mod monitored;
+pub mod sqlite;
+#[cfg(test)]
+mod tests;
+
+pub type SharedStorage = Arc<dyn Storage>;
+
+#[derive(Debug)]
+pub enum StorageError {
+    UsernameTaken,
+    RoomExists,
+    // The backend can't be reached right now, e.g. the database server is down. Worth retrying later
+    Unavailable(String),
+    Backend(String),
+}
+
+// Everything the server persists, implemented once per backend selectable in the config
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // Cheapest possible round trip to the backend, fails with `Unavailable` while it is down
+    async fn ping(&self) -> Result<(), StorageError>;
+
+    async fn insert_user(&self, user: &User) -> Result<(), StorageError>;
+    // Looked up by username only, the password hash is checked by the caller
+    async fn select_user(&self, username: &str) -> Result<Option<User>, StorageError>;
+    async fn update_password(&self, username: &str, password: &str) -> Result<(), StorageError>;
+    async fn user_exists(&self, username: &str) -> Result<bool, StorageError>;
+
+    async fn insert_message(&self, message: &StoredMessage) -> Result<(), StorageError>;
+    // Newest `limit` messages of a room older than the (timestamp, id) cursor, oldest first
+    async fn messages_before(&self, room: &str, before: Option<(DateTime, ObjectId)>, limit: i64) -> Result<Vec<StoredMessage>, StorageError>;
+
+    async fn insert_direct_message(&self, message: &StoredDirectMessage) -> Result<(), StorageError>;
+    // Messages sent to `username` while they were offline, oldest first
+    async fn pending_direct_messages(&self, username: &str) -> Result<Vec<StoredDirectMessage>, StorageError>;
+    async fn mark_direct_messages_delivered(&self, ids: &[ObjectId]) -> Result<(), StorageError>;
+
+    async fn insert_room(&self, room: &Room) -> Result<(), StorageError>;
+    async fn select_room(&self, name: &str) -> Result<Option<Room>, StorageError>;
+    // Sorted by name
+    async fn list_rooms(&self) -> Result<Vec<Room>, StorageError>;
+
+    async fn insert_session(&self, session: &StoredSession) -> Result<(), StorageError>;
+    async fn select_session(&self, token_hash: &str) -> Result<Option<StoredSession>, StorageError>;
+    // Oldest first
+    async fn list_sessions(&self, username: &str) -> Result<Vec<StoredSession>, StorageError>;
+    // Returns false if the user has no session with that id
+    async fn delete_session(&self, username: &str, id: ObjectId) -> Result<bool, StorageError>;
+    async fn delete_expired_sessions(&self) -> Result<(), StorageError>;
+
+    async fn latest_messages(&self, room: &str, limit: i64) -> Result<Vec<StoredMessage>, StorageError> {
+        self.messages_before(room, None, limit).await
+    }
+
+    // Creates the room unless it already exists, used for rooms the server relies on
+    async fn ensure_room(&self, name: &str) -> Result<(), StorageError> {
+        if self.select_room(name).await?.is_none() {
+            self.insert_room(&Room::new(name.to_string(), "server".to_string())).await?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::UsernameTaken => write!(f, "Username already exists"),
+            StorageError::RoomExists => write!(f, "Room already exists"),
+            StorageError::Unavailable(message) => write!(f, "Storage unavailable: {}", message),
+            StorageError::Backend(message) => write!(f, "{}", message),
+        }
+    }
+}
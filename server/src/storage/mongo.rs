@@ -0,0 +1,238 @@
+use async_trait::async_trait;
+use futures_util::TryStreamExt;
+use mongodb::{bson::{self, oid::ObjectId, DateTime, Document}, error::{ErrorKind, WriteFailure}, options::IndexOptions, IndexModel};
+use serde::de::DeserializeOwned;
+
+use crate::{connection::database::Database, message::{direct::StoredDirectMessage, message::StoredMessage}, room::room::Room, session::session::StoredSession, user::user::User};
+
+use super::{Storage, StorageError};
+
+// Server error code of an insert that violates a unique index
+const DUPLICATE_KEY: i32 = 11000;
+
+pub struct MongoStorage {
+    database: Database,
+}
+
+impl MongoStorage {
+    pub fn new(database: Database) -> Self {
+        Self { database }
+    }
+
+    // Unique indexes make concurrent inserts of the same user or room fail in the database itself,
+    // the others keep lookups and history pages off full collection scans. Existing ones are left as they are
+    pub async fn create_indexes(&self) -> Result<(), StorageError> {
+        let indexes = [
+            ("users", bson::doc! { "username": 1 }, true),
+            ("rooms", bson::doc! { "name": 1 }, true),
+            ("sessions", bson::doc! { "token_hash": 1 }, true),
+            ("sessions", bson::doc! { "username": 1, "created_at": 1 }, false),
+            ("messages", bson::doc! { "room": 1, "timestamp": 1, "_id": 1 }, false),
+            ("direct_messages", bson::doc! { "to": 1, "delivered": 1, "timestamp": 1 }, false),
+        ];
+        for (collection, keys, unique) in indexes {
+            let index = IndexModel::builder()
+                .keys(keys)
+                .options(IndexOptions::builder().unique(unique).build())
+                .build();
+            self.collection(collection)?.create_index(index).await.map_err(|err| match StorageError::from(err) {
+                StorageError::Backend(message) => StorageError::Backend(format!("Failed to create an index on {collection} => {message}")),
+                err => err,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn collection(&self, name: &str) -> Result<mongodb::Collection<Document>, StorageError> {
+        self.database.get_collection(name).ok_or_else(|| StorageError::Backend("Database is not connected".to_string()))
+    }
+}
+
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(&*err.kind, ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == DUPLICATE_KEY)
+}
+
+async fn collect<T: DeserializeOwned>(cursor: mongodb::Cursor<Document>) -> Result<Vec<T>, StorageError> {
+    let documents: Vec<Document> = cursor.try_collect().await?;
+    documents
+        .into_iter()
+        .map(|document| bson::from_document(document).map_err(StorageError::from))
+        .collect()
+}
+
+#[async_trait]
+impl Storage for MongoStorage {
+    async fn ping(&self) -> Result<(), StorageError> {
+        let database = self.database.get_database().ok_or_else(|| StorageError::Backend("Database is not connected".to_string()))?;
+        database.run_command(bson::doc! { "ping": 1 }).await?;
+        Ok(())
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), StorageError> {
+        let collection = self.collection("users")?;
+        match collection.insert_one(bson::to_document(user)?).await {
+            Err(err) if is_duplicate_key(&err) => Err(StorageError::UsernameTaken),
+            result => result.map(|_| ()).map_err(StorageError::from),
+        }
+    }
+
+    async fn select_user(&self, username: &str) -> Result<Option<User>, StorageError> {
+        let collection = self.collection("users")?;
+        let filter = bson::doc! { "username": username };
+        match collection.find_one(filter).await? {
+            Some(user) => Ok(Some(bson::from_document(user)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn update_password(&self, username: &str, password: &str) -> Result<(), StorageError> {
+        let collection = self.collection("users")?;
+        let filter = bson::doc! { "username": username };
+        collection.update_one(filter, bson::doc! { "$set": { "password": password } }).await?;
+        Ok(())
+    }
+
+    async fn user_exists(&self, username: &str) -> Result<bool, StorageError> {
+        let collection = self.collection("users")?;
+        let filter = bson::doc! { "username": username };
+        Ok(collection.find_one(filter).await?.is_some())
+    }
+
+    async fn insert_message(&self, message: &StoredMessage) -> Result<(), StorageError> {
+        let collection = self.collection("messages")?;
+        collection.insert_one(bson::to_document(message)?).await?;
+        Ok(())
+    }
+
+    async fn messages_before(&self, room: &str, before: Option<(DateTime, ObjectId)>, limit: i64) -> Result<Vec<StoredMessage>, StorageError> {
+        let collection = self.collection("messages")?;
+        let filter = match before {
+            Some((timestamp, id)) => bson::doc! {
+                "room": room,
+                "$or": [
+                    { "timestamp": { "$lt": timestamp } },
+                    { "timestamp": timestamp, "_id": { "$lt": id } },
+                ],
+            },
+            None => bson::doc! { "room": room },
+        };
+        let cursor = collection
+            .find(filter)
+            .sort(bson::doc! { "timestamp": -1, "_id": -1 })
+            .limit(limit)
+            .await?;
+
+        let mut messages: Vec<StoredMessage> = collect(cursor).await?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    async fn insert_direct_message(&self, message: &StoredDirectMessage) -> Result<(), StorageError> {
+        let collection = self.collection("direct_messages")?;
+        collection.insert_one(bson::to_document(message)?).await?;
+        Ok(())
+    }
+
+    async fn pending_direct_messages(&self, username: &str) -> Result<Vec<StoredDirectMessage>, StorageError> {
+        let collection = self.collection("direct_messages")?;
+        let filter = bson::doc! { "to": username, "delivered": false };
+        let cursor = collection
+            .find(filter)
+            .sort(bson::doc! { "timestamp": 1, "_id": 1 })
+            .await?;
+        collect(cursor).await
+    }
+
+    async fn mark_direct_messages_delivered(&self, ids: &[ObjectId]) -> Result<(), StorageError> {
+        let collection = self.collection("direct_messages")?;
+        let filter = bson::doc! { "_id": { "$in": ids } };
+        collection.update_many(filter, bson::doc! { "$set": { "delivered": true } }).await?;
+        Ok(())
+    }
+
+    async fn insert_room(&self, room: &Room) -> Result<(), StorageError> {
+        let collection = self.collection("rooms")?;
+        match collection.insert_one(bson::to_document(room)?).await {
+            Err(err) if is_duplicate_key(&err) => Err(StorageError::RoomExists),
+            result => result.map(|_| ()).map_err(StorageError::from),
+        }
+    }
+
+    async fn select_room(&self, name: &str) -> Result<Option<Room>, StorageError> {
+        let collection = self.collection("rooms")?;
+        let filter = bson::doc! { "name": name };
+        match collection.find_one(filter).await? {
+            Some(room) => Ok(Some(bson::from_document(room)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<Room>, StorageError> {
+        let collection = self.collection("rooms")?;
+        let cursor = collection
+            .find(bson::doc! {})
+            .sort(bson::doc! { "name": 1 })
+            .await?;
+        collect(cursor).await
+    }
+
+    async fn insert_session(&self, session: &StoredSession) -> Result<(), StorageError> {
+        let collection = self.collection("sessions")?;
+        collection.insert_one(bson::to_document(session)?).await?;
+        Ok(())
+    }
+
+    async fn select_session(&self, token_hash: &str) -> Result<Option<StoredSession>, StorageError> {
+        let collection = self.collection("sessions")?;
+        let filter = bson::doc! { "token_hash": token_hash };
+        match collection.find_one(filter).await? {
+            Some(session) => Ok(Some(bson::from_document(session)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_sessions(&self, username: &str) -> Result<Vec<StoredSession>, StorageError> {
+        let collection = self.collection("sessions")?;
+        let cursor = collection
+            .find(bson::doc! { "username": username })
+            .sort(bson::doc! { "created_at": 1 })
+            .await?;
+        collect(cursor).await
+    }
+
+    async fn delete_session(&self, username: &str, id: ObjectId) -> Result<bool, StorageError> {
+        let collection = self.collection("sessions")?;
+        let result = collection.delete_one(bson::doc! { "_id": id, "username": username }).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn delete_expired_sessions(&self) -> Result<(), StorageError> {
+        let collection = self.collection("sessions")?;
+        collection.delete_many(bson::doc! { "expires_at": { "$lte": DateTime::now() } }).await?;
+        Ok(())
+    }
+}
+
+impl From<mongodb::error::Error> for StorageError {
+    fn from(err: mongodb::error::Error) -> Self {
+        match *err.kind {
+            // No reachable server or the connection to it broke
+            ErrorKind::ServerSelection { .. } | ErrorKind::Io(_) | ErrorKind::ConnectionPoolCleared { .. } | ErrorKind::DnsResolve { .. } => {
+                StorageError::Unavailable(err.to_string())
+            },
+            _ => StorageError::Backend(err.to_string()),
+        }
+    }
+}
+
+impl From<bson::ser::Error> for StorageError {
+    fn from(err: bson::ser::Error) -> Self {
+        StorageError::Backend(err.to_string())
+    }
+}
+
+impl From<bson::de::Error> for StorageError {
+    fn from(err: bson::de::Error) -> Self {
+        StorageError::Backend(err.to_string())
+    }
+}
@@ -0,0 +1,177 @@
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc}, time::Duration};
+
+use async_trait::async_trait;
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use crate::{message::{direct::StoredDirectMessage, message::StoredMessage}, room::room::Room, session::session::StoredSession, user::user::User};
+
+use super::{SharedStorage, Storage, StorageError};
+
+// How often an unavailable backend is pinged to find out whether it is back
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+// Wraps a backend and notices when it goes away. Until it is back every call fails right away
+// with `Unavailable` instead of waiting for the backend to time out, and a probe pings it
+pub struct MonitoredStorage {
+    inner: SharedStorage,
+    available: Arc<AtomicBool>,
+}
+
+impl MonitoredStorage {
+    pub fn new(inner: SharedStorage) -> Self {
+        Self {
+            inner,
+            available: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    fn check(&self) -> Result<(), StorageError> {
+        if !self.available.load(Ordering::Relaxed) {
+            return Err(StorageError::Unavailable("Storage is down, waiting for it to come back".to_string()));
+        }
+        Ok(())
+    }
+
+    fn observe<T>(&self, result: Result<T, StorageError>) -> Result<T, StorageError> {
+        if let Err(StorageError::Unavailable(reason)) = &result {
+            // Only the call that notices the outage first starts the probe
+            if self.available.swap(false, Ordering::Relaxed) {
+                eprintln!("Storage became unavailable, serving in degraded mode: {}", reason);
+                tokio::spawn(probe(self.inner.clone(), self.available.clone()));
+            }
+        }
+        result
+    }
+}
+
+async fn probe(inner: SharedStorage, available: Arc<AtomicBool>) {
+    loop {
+        tokio::time::sleep(PROBE_INTERVAL).await;
+        if inner.ping().await.is_ok() {
+            available.store(true, Ordering::Relaxed);
+            println!("Storage is available again");
+            return;
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for MonitoredStorage {
+    async fn ping(&self) -> Result<(), StorageError> {
+        self.check()?;
+        self.observe(self.inner.ping().await)
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), StorageError> {
+        self.check()?;
+        self.observe(self.inner.insert_user(user).await)
+    }
+
+    async fn select_user(&self, username: &str) -> Result<Option<User>, StorageError> {
+        self.check()?;
+        self.observe(self.inner.select_user(username).await)
+    }
+
+    async fn update_password(&self, username: &str, password: &str) -> Result<(), StorageError> {
+        self.check()?;
+        self.observe(self.inner.update_password(username, password).await)
+    }
+
+    async fn user_exists(&self, username: &str) -> Result<bool, StorageError> {
+        self.check()?;
+        self.observe(self.inner.user_exists(username).await)
+    }
+
+    async fn insert_message(&self, message: &StoredMessage) -> Result<(), StorageError> {
+        self.check()?;
+        self.observe(self.inner.insert_message(message).await)
+    }
+
+    async fn messages_before(&self, room: &str, before: Option<(DateTime, ObjectId)>, limit: i64) -> Result<Vec<StoredMessage>, StorageError> {
+        self.check()?;
+        self.observe(self.inner.messages_before(room, before, limit).await)
+    }
+
+    async fn insert_direct_message(&self, message: &StoredDirectMessage) -> Result<(), StorageError> {
+        self.check()?;
+        self.observe(self.inner.insert_direct_message(message).await)
+    }
+
+    async fn pending_direct_messages(&self, username: &str) -> Result<Vec<StoredDirectMessage>, StorageError> {
+        self.check()?;
+        self.observe(self.inner.pending_direct_messages(username).await)
+    }
+
+    async fn mark_direct_messages_delivered(&self, ids: &[ObjectId]) -> Result<(), StorageError> {
+        self.check()?;
+        self.observe(self.inner.mark_direct_messages_delivered(ids).await)
+    }
+
+    async fn insert_room(&self, room: &Room) -> Result<(), StorageError> {
+        self.check()?;
+        self.observe(self.inner.insert_room(room).await)
+    }
+
+    async fn select_room(&self, name: &str) -> Result<Option<Room>, StorageError> {
+        self.check()?;
+        self.observe(self.inner.select_room(name).await)
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<Room>, StorageError> {
+        self.check()?;
+        self.observe(self.inner.list_rooms().await)
+    }
+
+    async fn insert_session(&self, session: &StoredSession) -> Result<(), StorageError> {
+        self.check()?;
+        self.observe(self.inner.insert_session(session).await)
+    }
+
+    async fn select_session(&self, token_hash: &str) -> Result<Option<StoredSession>, StorageError> {
+        self.check()?;
+        self.observe(self.inner.select_session(token_hash).await)
+    }
+
+    async fn list_sessions(&self, username: &str) -> Result<Vec<StoredSession>, StorageError> {
+        self.check()?;
+        self.observe(self.inner.list_sessions(username).await)
+    }
+
+    async fn delete_session(&self, username: &str, id: ObjectId) -> Result<bool, StorageError> {
+        self.check()?;
+        self.observe(self.inner.delete_session(username, id).await)
+    }
+
+    async fn delete_expired_sessions(&self) -> Result<(), StorageError> {
+        self.check()?;
+        self.observe(self.inner.delete_expired_sessions().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chat_protocol::{ErrorCode, ServerFrame};
+
+    use crate::{error::ServerError, storage::memory::MemoryStorage};
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn degrades_during_an_outage_and_recovers() {
+        let memory = Arc::new(MemoryStorage::new());
+        let storage = MonitoredStorage::new(memory.clone());
+        assert!(storage.ping().await.is_ok());
+
+        memory.set_available(false);
+        let err = storage.ping().await.unwrap_err();
+        assert!(matches!(err, StorageError::Unavailable(_)));
+        assert!(matches!(ServerError::from(err).to_frame(), Some(ServerFrame::Error { code: ErrorCode::ServiceDegraded, .. })));
+
+        // Calls keep failing fast until the probe has seen the backend again
+        memory.set_available(true);
+        assert!(matches!(storage.ping().await, Err(StorageError::Unavailable(_))));
+
+        tokio::time::sleep(PROBE_INTERVAL + Duration::from_millis(1)).await;
+        assert!(storage.ping().await.is_ok());
+    }
+}
@@ -0,0 +1,340 @@
+use std::{path::Path, sync::{Arc, Mutex, PoisonError}};
+
+use async_trait::async_trait;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, Row};
+
+use crate::{message::{direct::StoredDirectMessage, message::StoredMessage}, room::room::Room, session::session::StoredSession, user::user::User};
+
+use super::{Storage, StorageError};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS users (
+        username TEXT PRIMARY KEY,
+        password TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS messages (
+        id TEXT PRIMARY KEY,
+        room TEXT NOT NULL,
+        sender TEXT NOT NULL,
+        body TEXT NOT NULL,
+        timestamp INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS messages_by_room ON messages (room, timestamp, id);
+    CREATE TABLE IF NOT EXISTS direct_messages (
+        id TEXT PRIMARY KEY,
+        sender TEXT NOT NULL,
+        recipient TEXT NOT NULL,
+        body TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        delivered INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS direct_messages_by_recipient ON direct_messages (recipient, delivered);
+    CREATE TABLE IF NOT EXISTS rooms (
+        name TEXT PRIMARY KEY,
+        created_by TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sessions (
+        id TEXT PRIMARY KEY,
+        username TEXT NOT NULL,
+        token_hash TEXT NOT NULL UNIQUE,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS sessions_by_username ON sessions (username);
+";
+
+// Single file database for small deployments, ids are stored as ObjectId hex strings
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    // Opens or creates the database file and makes sure every table exists
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    // rusqlite is blocking, so every query runs on the blocking thread pool
+    async fn run<T, F>(&self, query: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T, StorageError> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || query(&connection.lock().unwrap_or_else(PoisonError::into_inner)))
+            .await
+            .map_err(|err| StorageError::Backend(err.to_string()))?
+    }
+}
+
+fn parse_id(id: String) -> rusqlite::Result<ObjectId> {
+    ObjectId::parse_str(&id).map_err(|err| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err)))
+}
+
+fn message_from_row(row: &Row) -> rusqlite::Result<StoredMessage> {
+    Ok(StoredMessage {
+        id: parse_id(row.get("id")?)?,
+        sender: row.get("sender")?,
+        body: row.get("body")?,
+        timestamp: DateTime::from_millis(row.get("timestamp")?),
+        room: row.get("room")?,
+    })
+}
+
+fn direct_message_from_row(row: &Row) -> rusqlite::Result<StoredDirectMessage> {
+    Ok(StoredDirectMessage {
+        id: parse_id(row.get("id")?)?,
+        from: row.get("sender")?,
+        to: row.get("recipient")?,
+        body: row.get("body")?,
+        timestamp: DateTime::from_millis(row.get("timestamp")?),
+        delivered: row.get("delivered")?,
+    })
+}
+
+fn room_from_row(row: &Row) -> rusqlite::Result<Room> {
+    Ok(Room {
+        name: row.get("name")?,
+        created_by: row.get("created_by")?,
+        created_at: DateTime::from_millis(row.get("created_at")?),
+    })
+}
+
+fn session_from_row(row: &Row) -> rusqlite::Result<StoredSession> {
+    Ok(StoredSession {
+        id: parse_id(row.get("id")?)?,
+        username: row.get("username")?,
+        token_hash: row.get("token_hash")?,
+        created_at: DateTime::from_millis(row.get("created_at")?),
+        expires_at: DateTime::from_millis(row.get("expires_at")?),
+    })
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn ping(&self) -> Result<(), StorageError> {
+        self.run(|connection| Ok(connection.execute_batch("SELECT 1")?)).await
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), StorageError> {
+        let user = user.clone();
+        self.run(move |connection| {
+            // Check if username already exists
+            let exists = connection
+                .query_row("SELECT 1 FROM users WHERE username = ?1", params![user.username], |_| Ok(()))
+                .optional()?
+                .is_some();
+            if exists {
+                return Err(StorageError::UsernameTaken);
+            }
+
+            connection.execute("INSERT INTO users (username, password) VALUES (?1, ?2)", params![user.username, user.password])?;
+            Ok(())
+        }).await
+    }
+
+    async fn select_user(&self, username: &str) -> Result<Option<User>, StorageError> {
+        let username = username.to_string();
+        self.run(move |connection| {
+            let user = connection
+                .query_row(
+                    "SELECT username, password FROM users WHERE username = ?1",
+                    params![username],
+                    |row| Ok(User::new(row.get("username")?, row.get("password")?)),
+                )
+                .optional()?;
+            Ok(user)
+        }).await
+    }
+
+    async fn update_password(&self, username: &str, password: &str) -> Result<(), StorageError> {
+        let username = username.to_string();
+        let password = password.to_string();
+        self.run(move |connection| {
+            connection.execute("UPDATE users SET password = ?2 WHERE username = ?1", params![username, password])?;
+            Ok(())
+        }).await
+    }
+
+    async fn user_exists(&self, username: &str) -> Result<bool, StorageError> {
+        let username = username.to_string();
+        self.run(move |connection| {
+            let exists = connection
+                .query_row("SELECT 1 FROM users WHERE username = ?1", params![username], |_| Ok(()))
+                .optional()?
+                .is_some();
+            Ok(exists)
+        }).await
+    }
+
+    async fn insert_message(&self, message: &StoredMessage) -> Result<(), StorageError> {
+        let message = message.clone();
+        self.run(move |connection| {
+            connection.execute(
+                "INSERT INTO messages (id, room, sender, body, timestamp) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![message.id.to_hex(), message.room, message.sender, message.body, message.timestamp.timestamp_millis()],
+            )?;
+            Ok(())
+        }).await
+    }
+
+    async fn messages_before(&self, room: &str, before: Option<(DateTime, ObjectId)>, limit: i64) -> Result<Vec<StoredMessage>, StorageError> {
+        let room = room.to_string();
+        self.run(move |connection| {
+            let mut messages = match before {
+                Some((timestamp, id)) => {
+                    let mut statement = connection.prepare(
+                        "SELECT * FROM messages
+                         WHERE room = ?1 AND (timestamp < ?2 OR (timestamp = ?2 AND id < ?3))
+                         ORDER BY timestamp DESC, id DESC LIMIT ?4",
+                    )?;
+                    let rows = statement.query_map(params![room, timestamp.timestamp_millis(), id.to_hex(), limit], message_from_row)?;
+                    rows.collect::<rusqlite::Result<Vec<StoredMessage>>>()?
+                },
+                None => {
+                    let mut statement = connection.prepare(
+                        "SELECT * FROM messages WHERE room = ?1 ORDER BY timestamp DESC, id DESC LIMIT ?2",
+                    )?;
+                    let rows = statement.query_map(params![room, limit], message_from_row)?;
+                    rows.collect::<rusqlite::Result<Vec<StoredMessage>>>()?
+                },
+            };
+            messages.reverse();
+            Ok(messages)
+        }).await
+    }
+
+    async fn insert_direct_message(&self, message: &StoredDirectMessage) -> Result<(), StorageError> {
+        let message = message.clone();
+        self.run(move |connection| {
+            connection.execute(
+                "INSERT INTO direct_messages (id, sender, recipient, body, timestamp, delivered) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![message.id.to_hex(), message.from, message.to, message.body, message.timestamp.timestamp_millis(), message.delivered],
+            )?;
+            Ok(())
+        }).await
+    }
+
+    async fn pending_direct_messages(&self, username: &str) -> Result<Vec<StoredDirectMessage>, StorageError> {
+        let username = username.to_string();
+        self.run(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT * FROM direct_messages WHERE recipient = ?1 AND delivered = 0 ORDER BY timestamp, id",
+            )?;
+            let rows = statement.query_map(params![username], direct_message_from_row)?;
+            Ok(rows.collect::<rusqlite::Result<Vec<StoredDirectMessage>>>()?)
+        }).await
+    }
+
+    async fn mark_direct_messages_delivered(&self, ids: &[ObjectId]) -> Result<(), StorageError> {
+        let ids = ids.iter().map(|id| id.to_hex()).collect::<Vec<String>>();
+        self.run(move |connection| {
+            let mut statement = connection.prepare("UPDATE direct_messages SET delivered = 1 WHERE id = ?1")?;
+            for id in ids {
+                statement.execute(params![id])?;
+            }
+            Ok(())
+        }).await
+    }
+
+    async fn insert_room(&self, room: &Room) -> Result<(), StorageError> {
+        let room = room.clone();
+        self.run(move |connection| {
+            // Check if room already exists
+            let exists = connection
+                .query_row("SELECT 1 FROM rooms WHERE name = ?1", params![room.name], |_| Ok(()))
+                .optional()?
+                .is_some();
+            if exists {
+                return Err(StorageError::RoomExists);
+            }
+
+            connection.execute(
+                "INSERT INTO rooms (name, created_by, created_at) VALUES (?1, ?2, ?3)",
+                params![room.name, room.created_by, room.created_at.timestamp_millis()],
+            )?;
+            Ok(())
+        }).await
+    }
+
+    async fn select_room(&self, name: &str) -> Result<Option<Room>, StorageError> {
+        let name = name.to_string();
+        self.run(move |connection| {
+            let room = connection
+                .query_row("SELECT * FROM rooms WHERE name = ?1", params![name], room_from_row)
+                .optional()?;
+            Ok(room)
+        }).await
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<Room>, StorageError> {
+        self.run(|connection| {
+            let mut statement = connection.prepare("SELECT * FROM rooms ORDER BY name")?;
+            let rows = statement.query_map([], room_from_row)?;
+            Ok(rows.collect::<rusqlite::Result<Vec<Room>>>()?)
+        }).await
+    }
+
+    async fn insert_session(&self, session: &StoredSession) -> Result<(), StorageError> {
+        let session = session.clone();
+        self.run(move |connection| {
+            connection.execute(
+                "INSERT INTO sessions (id, username, token_hash, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![session.id.to_hex(), session.username, session.token_hash, session.created_at.timestamp_millis(), session.expires_at.timestamp_millis()],
+            )?;
+            Ok(())
+        }).await
+    }
+
+    async fn select_session(&self, token_hash: &str) -> Result<Option<StoredSession>, StorageError> {
+        let token_hash = token_hash.to_string();
+        self.run(move |connection| {
+            let session = connection
+                .query_row("SELECT * FROM sessions WHERE token_hash = ?1", params![token_hash], session_from_row)
+                .optional()?;
+            Ok(session)
+        }).await
+    }
+
+    async fn list_sessions(&self, username: &str) -> Result<Vec<StoredSession>, StorageError> {
+        let username = username.to_string();
+        self.run(move |connection| {
+            let mut statement = connection.prepare("SELECT * FROM sessions WHERE username = ?1 ORDER BY created_at, id")?;
+            let rows = statement.query_map(params![username], session_from_row)?;
+            Ok(rows.collect::<rusqlite::Result<Vec<StoredSession>>>()?)
+        }).await
+    }
+
+    async fn delete_session(&self, username: &str, id: ObjectId) -> Result<bool, StorageError> {
+        let username = username.to_string();
+        self.run(move |connection| {
+            let deleted = connection.execute("DELETE FROM sessions WHERE id = ?1 AND username = ?2", params![id.to_hex(), username])?;
+            Ok(deleted > 0)
+        }).await
+    }
+
+    async fn delete_expired_sessions(&self) -> Result<(), StorageError> {
+        self.run(|connection| {
+            connection.execute("DELETE FROM sessions WHERE expires_at <= ?1", params![DateTime::now().timestamp_millis()])?;
+            Ok(())
+        }).await
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        match err.sqlite_error_code() {
+            // The file can't be opened or is locked by someone else, both usually pass
+            Some(ErrorCode::CannotOpen | ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked | ErrorCode::SystemIoFailure) => {
+                StorageError::Unavailable(err.to_string())
+            },
+            _ => StorageError::Backend(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// The same checks run against every backend that works inside the test process,
+// so their behaviour can't drift apart. MongoDB needs a server and isn't covered here
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use crate::{message::message::StoredMessage, room::room::Room, session::session::StoredSession, user::user::User};
+
+use super::{memory::MemoryStorage, sqlite::SqliteStorage, SharedStorage, StorageError};
+
+fn backends() -> Vec<(&'static str, SharedStorage)> {
+    vec![
+        ("memory", Arc::new(MemoryStorage::new())),
+        ("sqlite", Arc::new(SqliteStorage::open(Path::new(":memory:")).unwrap())),
+    ]
+}
+
+fn message(room: &str, timestamp: i64) -> StoredMessage {
+    StoredMessage {
+        id: ObjectId::new(),
+        sender: "alice".to_string(),
+        body: format!("at {timestamp}"),
+        timestamp: DateTime::from_millis(timestamp),
+        room: room.to_string(),
+    }
+}
+
+fn ids(messages: &[StoredMessage]) -> Vec<ObjectId> {
+    messages.iter().map(|message| message.id).collect()
+}
+
+#[tokio::test]
+async fn history_pages_follow_timestamp_then_id() {
+    for (backend, storage) in backends() {
+        // Newer ids with an older timestamp, and a run of messages sharing one timestamp
+        let mut messages = vec![message("general", 2_000), message("general", 2_000), message("general", 2_000)];
+        messages.extend([message("general", 1_000), message("general", 1_000), message("general", 2_000)]);
+        for message in &messages {
+            storage.insert_message(message).await.unwrap();
+        }
+        storage.insert_message(&message("other", 1_500)).await.unwrap();
+        messages.sort_by_key(|message| (message.timestamp, message.id));
+
+        assert_eq!(ids(&storage.latest_messages("general", 3).await.unwrap()), ids(&messages[3..]), "{backend}");
+
+        // Paging back two at a time from the newest must visit every message exactly once.
+        // Bounded, a cursor that doesn't move would otherwise page forever
+        let mut paged = Vec::new();
+        let mut before = None;
+        for _ in 0..=messages.len() {
+            let page = storage.messages_before("general", before, 2).await.unwrap();
+            let Some(oldest) = page.first() else {
+                break;
+            };
+            before = Some((oldest.timestamp, oldest.id));
+            paged.splice(0..0, page);
+        }
+        assert_eq!(ids(&paged), ids(&messages), "{backend}");
+
+        let oldest = &messages[0];
+        assert!(storage.messages_before("general", Some((oldest.timestamp, oldest.id)), 10).await.unwrap().is_empty(), "{backend}");
+        assert!(storage.messages_before("general", None, 0).await.unwrap().is_empty(), "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn duplicate_users_and_rooms_are_refused() {
+    for (backend, storage) in backends() {
+        storage.insert_user(&User::new("alice".to_string(), "first".to_string())).await.unwrap();
+        let duplicate = storage.insert_user(&User::new("alice".to_string(), "second".to_string())).await;
+        assert!(matches!(duplicate, Err(StorageError::UsernameTaken)), "{backend}");
+        assert_eq!(storage.select_user("alice").await.unwrap().unwrap().password, "first", "{backend}");
+
+        storage.insert_room(&Room::new("lobby".to_string(), "alice".to_string())).await.unwrap();
+        let duplicate = storage.insert_room(&Room::new("lobby".to_string(), "bob".to_string())).await;
+        assert!(matches!(duplicate, Err(StorageError::RoomExists)), "{backend}");
+
+        storage.ensure_room("lobby").await.unwrap();
+        let rooms = storage.list_rooms().await.unwrap();
+        assert_eq!(rooms.len(), 1, "{backend}");
+        assert_eq!(rooms[0].created_by, "alice", "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn sessions_can_be_deleted_and_expire() {
+    for (backend, storage) in backends() {
+        let (first, _) = StoredSession::issue("alice".to_string(), Duration::from_secs(60));
+        let (second, _) = StoredSession::issue("alice".to_string(), Duration::from_secs(60));
+        let (expired, _) = StoredSession::issue("alice".to_string(), Duration::ZERO);
+        let (other, _) = StoredSession::issue("bob".to_string(), Duration::from_secs(60));
+        for session in [&first, &second, &expired, &other] {
+            storage.insert_session(session).await.unwrap();
+        }
+
+        let found = storage.select_session(&first.token_hash).await.unwrap().unwrap();
+        assert_eq!(found.id, first.id, "{backend}");
+        assert_eq!(storage.list_sessions("alice").await.unwrap().len(), 3, "{backend}");
+
+        // Only the owner can delete a session, and only once
+        assert!(!storage.delete_session("bob", first.id).await.unwrap(), "{backend}");
+        assert!(storage.delete_session("alice", first.id).await.unwrap(), "{backend}");
+        assert!(!storage.delete_session("alice", first.id).await.unwrap(), "{backend}");
+        assert!(storage.select_session(&first.token_hash).await.unwrap().is_none(), "{backend}");
+
+        storage.delete_expired_sessions().await.unwrap();
+        assert!(storage.select_session(&expired.token_hash).await.unwrap().is_none(), "{backend}");
+        let remaining = storage.list_sessions("alice").await.unwrap();
+        assert_eq!(remaining.iter().map(|session| session.id).collect::<Vec<_>>(), vec![second.id], "{backend}");
+        assert_eq!(storage.list_sessions("bob").await.unwrap().len(), 1, "{backend}");
+    }
+}
@@ -1 +1,4 @@
-pub mod user;
\ No newline at end of file
+pub mod password;
+pub mod scram;
+#[allow(clippy::module_inception)]
+pub mod user;
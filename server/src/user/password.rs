@@ -0,0 +1,39 @@
+use argon2::{password_hash::{PasswordHash, PasswordVerifier}, Argon2};
+use subtle::ConstantTimeEq;
+
+// Accounts from before challenge-response login store the SHA-256 digest the old client sent,
+// either as is or, since server-side hashing, as an Argon2 PHC string of that digest
+pub fn verify_legacy(digest: &str, stored: &str) -> bool {
+    match PasswordHash::new(stored) {
+        // verify_password uses the parameters stored in the hash and compares in constant time
+        Ok(hash) => Argon2::default().verify_password(digest.as_bytes(), &hash).is_ok(),
+        Err(_) => bool::from(digest.as_bytes().ct_eq(stored.as_bytes())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+
+    use super::*;
+
+    // SHA-256 of "password", what the old client sent
+    const DIGEST: &str = "5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8";
+
+    #[test]
+    fn plain_digests_are_compared() {
+        assert!(verify_legacy(DIGEST, DIGEST));
+        assert!(!verify_legacy(&DIGEST.replace('5', "6"), DIGEST));
+        assert!(!verify_legacy("", DIGEST));
+    }
+
+    #[test]
+    fn argon2_hashes_of_the_digest_are_verified() {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default().hash_password(DIGEST.as_bytes(), &salt).unwrap().to_string();
+        assert!(verify_legacy(DIGEST, &hash));
+        assert!(!verify_legacy(&DIGEST.replace('5', "6"), &hash));
+        // The hash itself is not a password
+        assert!(!verify_legacy(&hash, &hash));
+    }
+}
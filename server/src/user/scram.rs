@@ -0,0 +1,205 @@
+use std::sync::OnceLock;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_protocol::scram::{self, decode_key, hmac, Credentials, KdfParams, KEY_LEN, SALT_LEN};
+
+const PREFIX: &str = "$scram-argon2id$";
+
+// Server side of the challenge-response login, stored as
+// $scram-argon2id$m=<memory_kib>,t=<iterations>,p=<parallelism>$<salt>$<stored_key>$<server_key>
+#[derive(Debug, Clone)]
+pub struct StoredCredentials {
+    pub salt: String,
+    pub params: KdfParams,
+    stored_key: Vec<u8>,
+    server_key: Vec<u8>,
+}
+
+impl StoredCredentials {
+    // Validates credentials sent by a client
+    pub fn from_wire(credentials: &Credentials) -> Result<Self, String> {
+        let salt = STANDARD.decode(&credentials.salt).map_err(|_| "Salt is not valid base64")?;
+        if salt.len() < SALT_LEN {
+            return Err(format!("Salt must be at least {SALT_LEN} bytes"));
+        }
+        if !credentials.params.at_least(&KdfParams::RECOMMENDED) {
+            return Err("Key derivation parameters are too weak".to_string());
+        }
+        // Clients refuse to derive keys above this, the account could never log in again
+        if !credentials.params.at_most(&KdfParams::MAXIMUM) {
+            return Err("Key derivation parameters are too expensive".to_string());
+        }
+        let stored_key = decode_key(&credentials.stored_key).ok_or("Stored key is not a base64 SHA-256 value")?;
+        let server_key = decode_key(&credentials.server_key).ok_or("Server key is not a base64 SHA-256 value")?;
+
+        Ok(Self {
+            salt: credentials.salt.clone(),
+            params: credentials.params,
+            stored_key,
+            server_key,
+        })
+    }
+
+    // None for anything that isn't in the format written by `encode`, e.g. accounts from before SCRAM
+    pub fn parse(stored: &str) -> Option<Self> {
+        let mut parts = stored.strip_prefix(PREFIX)?.split('$');
+        let params = parse_params(parts.next()?)?;
+        let salt = parts.next()?.to_string();
+        let stored_key = decode_key(parts.next()?)?;
+        let server_key = decode_key(parts.next()?)?;
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Self { salt, params, stored_key, server_key })
+    }
+
+    pub fn encode(&self) -> String {
+        format!(
+            "{PREFIX}m={},t={},p={}${}${}${}",
+            self.params.memory_kib,
+            self.params.iterations,
+            self.params.parallelism,
+            self.salt,
+            STANDARD.encode(&self.stored_key),
+            STANDARD.encode(&self.server_key),
+        )
+    }
+
+    // Checks the client proof and returns the base64 server signature if it is valid
+    pub fn verify_proof(&self, auth_message: &str, proof: &str) -> Option<String> {
+        scram::verify_proof(&self.stored_key, &self.server_key, auth_message, proof)
+    }
+
+    // Credentials derived with weaker parameters than we currently recommend
+    pub fn is_outdated(&self) -> bool {
+        !self.params.at_least(&KdfParams::RECOMMENDED)
+    }
+}
+
+// Salt handed out for unknown usernames. It is stable per username for the lifetime of the
+// process, so the challenge looks the same as for a real account and doesn't reveal which exist
+pub fn decoy_salt(username: &str) -> String {
+    static SECRET: OnceLock<[u8; KEY_LEN]> = OnceLock::new();
+    let secret = SECRET.get_or_init(rand::random);
+    STANDARD.encode(&hmac(secret, username.as_bytes())[..SALT_LEN])
+}
+
+fn parse_params(params: &str) -> Option<KdfParams> {
+    let mut memory_kib = None;
+    let mut iterations = None;
+    let mut parallelism = None;
+    for param in params.split(',') {
+        let (name, value) = param.split_once('=')?;
+        let value = value.parse::<u32>().ok()?;
+        match name {
+            "m" => memory_kib = Some(value),
+            "t" => iterations = Some(value),
+            "p" => parallelism = Some(value),
+            _ => return None,
+        }
+    }
+
+    Some(KdfParams {
+        memory_kib: memory_kib?,
+        iterations: iterations?,
+        parallelism: parallelism?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chat_protocol::scram::{auth_message, derive_credentials, login_proof, nonce};
+
+    use super::*;
+
+    // Cheap enough to keep the tests fast, `parse` takes any parameters
+    const CHEAP: KdfParams = KdfParams { memory_kib: 1024, iterations: 1, parallelism: 1 };
+
+    fn wire(params: KdfParams) -> Credentials {
+        Credentials {
+            salt: STANDARD.encode([1u8; SALT_LEN]),
+            params,
+            stored_key: STANDARD.encode([2u8; KEY_LEN]),
+            server_key: STANDARD.encode([3u8; KEY_LEN]),
+        }
+    }
+
+    fn stored(credentials: &Credentials) -> String {
+        let params = credentials.params;
+        format!(
+            "{PREFIX}m={},t={},p={}${}${}${}",
+            params.memory_kib, params.iterations, params.parallelism, credentials.salt, credentials.stored_key, credentials.server_key,
+        )
+    }
+
+    #[test]
+    fn encode_parse_round_trip() {
+        let credentials = StoredCredentials::from_wire(&wire(KdfParams::RECOMMENDED)).unwrap();
+        let encoded = credentials.encode();
+        assert_eq!(encoded, stored(&wire(KdfParams::RECOMMENDED)));
+
+        let parsed = StoredCredentials::parse(&encoded).unwrap();
+        assert_eq!(parsed.salt, credentials.salt);
+        assert_eq!(parsed.params, credentials.params);
+        assert_eq!((&parsed.stored_key, &parsed.server_key), (&credentials.stored_key, &credentials.server_key));
+        assert_eq!(parsed.encode(), encoded);
+        assert!(!parsed.is_outdated());
+    }
+
+    #[test]
+    fn malformed_and_legacy_strings_are_not_parsed() {
+        let valid = stored(&wire(KdfParams::RECOMMENDED));
+        let rejected = [
+            // SHA-256 digest and Argon2 PHC string of accounts from before SCRAM
+            "5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8".to_string(),
+            "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaGhhc2hoYXNoaGFzaA".to_string(),
+            String::new(),
+            format!("{valid}$extra"),
+            valid.rsplit_once('$').unwrap().0.to_string(),
+            valid.replace("m=19456,", ""),
+            valid.replace("p=1", "p=1,x=2"),
+            valid.replace("t=2", "t=two"),
+            valid.replace(&STANDARD.encode([2u8; KEY_LEN]), &STANDARD.encode([2u8; KEY_LEN - 1])),
+            valid.replace(&STANDARD.encode([3u8; KEY_LEN]), "not base64!"),
+        ];
+        for stored in rejected {
+            assert!(StoredCredentials::parse(&stored).is_none(), "{stored}");
+        }
+    }
+
+    #[test]
+    fn weak_expensive_or_malformed_wire_credentials_are_rejected() {
+        let weak = KdfParams { iterations: 1, ..KdfParams::RECOMMENDED };
+        let expensive = KdfParams { memory_kib: KdfParams::MAXIMUM.memory_kib + 1, ..KdfParams::RECOMMENDED };
+        assert!(StoredCredentials::from_wire(&wire(weak)).is_err());
+        assert!(StoredCredentials::from_wire(&wire(expensive)).is_err());
+
+        let short_salt = Credentials { salt: STANDARD.encode([1u8; SALT_LEN - 1]), ..wire(KdfParams::RECOMMENDED) };
+        let bad_key = Credentials { stored_key: "not base64!".to_string(), ..wire(KdfParams::RECOMMENDED) };
+        assert!(StoredCredentials::from_wire(&short_salt).is_err());
+        assert!(StoredCredentials::from_wire(&bad_key).is_err());
+    }
+
+    #[test]
+    fn stored_credentials_verify_a_login() {
+        let credentials = StoredCredentials::parse(&stored(&derive_credentials("correct horse", CHEAP).unwrap())).unwrap();
+        assert!(credentials.is_outdated());
+
+        let client_nonce = nonce();
+        let nonce = format!("{client_nonce}{}", nonce());
+        let auth_message = auth_message("alice", &nonce, &credentials.salt);
+        let proof = login_proof("alice", "correct horse", &client_nonce, &nonce, &credentials.salt, credentials.params).unwrap();
+        assert_eq!(credentials.verify_proof(&auth_message, &proof.proof), Some(proof.server_signature));
+
+        let wrong = login_proof("alice", "battery staple", &client_nonce, &nonce, &credentials.salt, credentials.params).unwrap();
+        assert!(credentials.verify_proof(&auth_message, &wrong.proof).is_none());
+    }
+
+    #[test]
+    fn decoy_salts_are_stable_per_username() {
+        assert_eq!(decoy_salt("alice"), decoy_salt("alice"));
+        assert_ne!(decoy_salt("alice"), decoy_salt("bob"));
+        assert_eq!(STANDARD.decode(decoy_salt("alice")).unwrap().len(), SALT_LEN);
+    }
+}
@@ -1,172 +1,340 @@
-use crossterm::event::{self, Event, KeyCode};
+use chat_protocol::{ClientFrame, ServerFrame, SessionToken};
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent};
+use futures_util::StreamExt;
 use ratatui::DefaultTerminal;
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
-use std::{io::Result, time::Duration};
+use std::{future, io::Result};
 
-use crate::frames::{custom::{chat_frame::ChatFrame, login_frame::LoginFrame, register_frame::RegisterFrame}, custom_frame::CustomFrame};
+use crate::{config::{ClientConfig, Profile, ServerOptions}, connection::{self, Connection, Socket, SocketEvent}, frames::{custom::{chat_frame::ChatFrame, login_frame::LoginFrame, profiles_frame::ProfilesFrame, register_frame::RegisterFrame}, custom_frame::CustomFrame}, reconnect::Reconnect, session_store::{self, RememberedSession}};
 
 pub struct App {
     terminal: DefaultTerminal,
     exit: bool,
     pub app_state: AppState,
-    pub socket: Option<WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>>,
+    pub connection: Option<Connection>,
     pub username: String,
+    // Token of the current login, used to resume it on a new socket
+    pub session: Option<SessionToken>,
+    pub server: ServerOptions,
+    // Set while the connection is gone and the session is being resumed
+    pub reconnect: Option<Reconnect>,
+    // Name of the saved profile the login screen was opened for, if any
+    pub profile: Option<String>,
+    // As loaded at startup, frames take their defaults from it
+    pub config: ClientConfig,
 }
 
 impl App {
 
-    pub fn new(terminal: DefaultTerminal) -> Self {
+    pub fn new(terminal: DefaultTerminal, config: ClientConfig) -> Self {
         Self {
             terminal,
             exit: false,
-            app_state: AppState::Login(LoginFrame::new()),
-            socket: None,
+            app_state: AppState::Login(LoginFrame::new(config.server.clone(), config.username.clone(), config.ui.show_password)),
+            connection: None,
             username: String::new(),
+            session: None,
+            server: config.server_options(),
+            reconnect: None,
+            profile: None,
+            config,
         }
     }
 
+    // Redraws after every key press and every frame from the server, whichever comes first
     pub async fn run(&mut self) -> Result<()> {
+        let mut terminal_events = EventStream::new();
         while !self.exit {
             self.render_tui();
-            self.handle_input().await;
-            // self.connection(); How I could implement this to thread without cloning?!?!?! I give up
+            tokio::select! {
+                event = terminal_events.next() => match event {
+                    Some(Ok(Event::Key(key_event))) => self.handle_key(key_event).await,
+                    // Resizes only need the redraw
+                    Some(Ok(_)) => {},
+                    Some(Err(err)) => return Err(err),
+                    None => self.exit = true,
+                },
+                Some(event) = next_socket_event(&mut self.connection) => self.handle_socket_event(event),
+                _ = next_reconnect_tick(&self.reconnect) => self.reconnect_tick().await,
+            }
         }
         Ok(())
     }
-    
+
     fn render_tui(&mut self) {
+        let ui = &self.config.ui;
         let _ = self.terminal.draw(|frame: &mut ratatui::Frame<'_>| {
             match self.app_state {
                 AppState::Login(ref login_frame) => {
-                    login_frame.render(frame);
+                    login_frame.render(frame, ui);
                 },
                 AppState::Chat(ref chat_frame) => {
-                    chat_frame.render(frame);
+                    chat_frame.render(frame, ui);
                 }
                 AppState::Register(ref register_frame) => {
-                    register_frame.render(frame);
+                    register_frame.render(frame, ui);
+                },
+                AppState::Profiles(ref profiles_frame) => {
+                    profiles_frame.render(frame, ui);
                 },   
             }
         });
     }
 
-    async fn handle_input(&mut self) {
-        if event::poll(Duration::from_millis(100)).unwrap() {
-            if let Event::Key(key_event) = event::read().unwrap() {
-                match key_event.code {
-                    KeyCode::Esc => {
-                        self.exit = true;
+    async fn handle_key(&mut self, key_event: KeyEvent) {
+        match key_event.code {
+            KeyCode::Esc => {
+                match self.app_state {
+                    AppState::Profiles(ref mut profiles_frame) if profiles_frame.is_editing() => profiles_frame.cancel_edit(),
+                    _ => self.exit = true,
+                }
+            },
+            KeyCode::Char(c) => {
+                match self.app_state {
+                    AppState::Login(ref mut login_frame) => {
+                        login_frame.input(c);
                     },
-                    KeyCode::Char(c) => {
-                        match self.app_state {
-                            AppState::Login(ref mut login_frame) => {
-                                login_frame.input(c);
-                            },
-                            AppState::Chat(ref mut chat_frame) => {
-                                chat_frame.input(c);
-                            }
-                            AppState::Register(ref mut register_frame) => {
-                                register_frame.input(c);
-                            },
-                        }
+                    AppState::Chat(ref mut chat_frame) => {
+                        chat_frame.input(c);
+                    }
+                    AppState::Register(ref mut register_frame) => {
+                        register_frame.input(c);
                     },
-
-                    KeyCode::Tab => {
-                        match self.app_state {
-                            AppState::Login(ref mut login_frame) => login_frame.focus = !login_frame.focus,
-                            AppState::Chat(ref mut chat_frame ) => chat_frame.change_focus(),
-                            AppState::Register(ref mut register_frame) => register_frame.change_focus(),
-                        }
+                    AppState::Profiles(ref mut profiles_frame) => {
+                        let mut profiles_frame = std::mem::take(profiles_frame);
+                        profiles_frame.input(c, self);
+                        self.app_state = AppState::Profiles(profiles_frame);
                     },
-                    KeyCode::Backspace => {
-                        match self.app_state {
-                            AppState::Login(ref mut login_frame) => login_frame.backspace(),
-                            AppState::Chat(ref mut chat_frame) => chat_frame.backspace(),
-                            AppState::Register(ref mut register_frame) => register_frame.backspace(),
-                        }
+                }
+            },
+
+            KeyCode::Tab => {
+                match self.app_state {
+                    AppState::Login(ref mut login_frame) => login_frame.change_focus(),
+                    AppState::Chat(ref mut chat_frame ) => chat_frame.change_focus(),
+                    AppState::Register(ref mut register_frame) => register_frame.change_focus(),
+                    AppState::Profiles(ref mut profiles_frame) => profiles_frame.change_focus(),
+                }
+            },
+            KeyCode::Up => {
+                if let AppState::Chat(ref mut chat_frame) = self.app_state {
+                    // Scrolling past the top pulls in older messages from the server
+                    if chat_frame.focus && chat_frame.scroll_up() {
+                        let mut mem_chat = std::mem::take(chat_frame);
+                        mem_chat.load_older_messages(self).await;
+                        self.app_state = AppState::Chat(mem_chat);
+                    }
+                } else if let AppState::Profiles(ref mut profiles_frame) = self.app_state {
+                    profiles_frame.up();
+                }
+            },
+            KeyCode::Down => {
+                match self.app_state {
+                    AppState::Chat(ref mut chat_frame) => chat_frame.scroll_down(),
+                    AppState::Profiles(ref mut profiles_frame) => profiles_frame.down(),
+                    _ => {}
+                }
+            },
+            KeyCode::Backspace => {
+                match self.app_state {
+                    AppState::Login(ref mut login_frame) => login_frame.backspace(),
+                    AppState::Chat(ref mut chat_frame) => chat_frame.backspace(),
+                    AppState::Register(ref mut register_frame) => register_frame.backspace(),
+                    AppState::Profiles(ref mut profiles_frame) => profiles_frame.backspace(),
+                }
+            },
+            KeyCode::F(1) => {
+                match self.app_state {
+                    AppState::Login(ref mut login_frame) => login_frame.toggle_password_visibility(),
+                    AppState::Register(ref mut register_frame) => register_frame.toggle_password_visibility(),
+                    AppState::Chat(_) | AppState::Profiles(_) => {}
+                }
+            },
+            KeyCode::F(2) => {
+                match self.app_state {
+                    AppState::Login(ref login_frame) => {
+                        // Registering happens on whatever server the login screen points at
+                        self.server.url = login_frame.server.trim().to_string();
+                        self.app_state = AppState::Register(RegisterFrame::new(self.config.ui.show_password));
                     },
-                    KeyCode::F(1) => {
-                        match self.app_state {
-                            AppState::Login(ref mut login_frame) => login_frame.toggle_password_visibility(),
-                            AppState::Register(ref mut register_frame) => register_frame.toggle_password_visibility(),
-                            AppState::Chat(_) => {}
-                        }
+                    AppState::Register(_) => {
+                        let username = self.profile.as_deref().and_then(|name| self.config.profile(name))
+                            .map_or_else(|| self.config.username.clone(), |profile| profile.username.clone());
+                        self.app_state = AppState::Login(LoginFrame::new(self.server.url.clone(), username, self.config.ui.show_password));
                     },
-                    KeyCode::F(2) => {
-                        match self.app_state {
-                            AppState::Login(_) => {
-                                self.app_state = AppState::Register(RegisterFrame::new());
-                            },
-                            AppState::Register(_) => {
-                                self.app_state = AppState::Login(LoginFrame::new());
+                    _ => {}
+                }
+            },
+            KeyCode::F(3) => {
+                match self.app_state {
+                    AppState::Login(_) => self.open_profiles(),
+                    AppState::Profiles(ref profiles_frame) if !profiles_frame.is_editing() => self.open_profile(None).await,
+                    _ => {}
+                }
+            },
+            KeyCode::Enter => {
+                match self.app_state {
+                    AppState::Login(ref mut login_frame) => {
+                        let mut login_frame = std::mem::take(login_frame);
+                        match login_frame.submit(self).await {
+                            // submit already switched to the chat frame
+                            Ok(_) => {},
+                            Err(err) => {
+                                login_frame.error_message = Some(err);
+                                self.app_state = AppState::Login(login_frame);
                             },
-                            _ => {}
-                        }
+                        };
                     },
-                    KeyCode::Enter => {
-                        match self.app_state {
-                            AppState::Login(ref mut login_frame) => {
-                                let mut login_frame = std::mem::take(login_frame);
-                                match login_frame.submit(self).await {
-                                    Ok(_) => {
-                                        let chat_frame = ChatFrame::new();
-                                        self.username = login_frame.username;
-                                        self.app_state = AppState::Chat(chat_frame);
-                                    },
-                                    Err(err) => {
-                                        login_frame.error_message = Some(err);
-                                        self.app_state = AppState::Login(login_frame);
-                                    },
-                                };
-                            },
-                            AppState::Chat(ref mut chat_frame) => {
-                                let mut mem_chat = std::mem::take(chat_frame);
-                                mem_chat.submit_message(self).await;
-                                self.app_state = AppState::Chat(mem_chat);
-                            },
-                            AppState::Register(ref mut register_frame) => {
-                                let mut register_frame = std::mem::take(register_frame);
-                                match register_frame.submit(self).await {
-                                    Ok(_) => {
-                                        let chat_frame = ChatFrame::new();
-                                        self.username = register_frame.username;
-                                        self.app_state = AppState::Chat(chat_frame);
-                                    },
-                                    Err(err) => {
-                                        register_frame.error_message = Some(err);
-                                        self.app_state = AppState::Register(register_frame);
-                                    },
-                                };
+                    AppState::Chat(ref mut chat_frame) => {
+                        let mut mem_chat = std::mem::take(chat_frame);
+                        mem_chat.submit_message(self).await;
+                        self.app_state = AppState::Chat(mem_chat);
+                    },
+                    AppState::Register(ref mut register_frame) => {
+                        let mut register_frame = std::mem::take(register_frame);
+                        match register_frame.submit(self).await {
+                            // submit already switched to the chat frame
+                            Ok(_) => {},
+                            Err(err) => {
+                                register_frame.error_message = Some(err);
+                                self.app_state = AppState::Register(register_frame);
                             },
+                        };
+                    },
+                    AppState::Profiles(ref mut profiles_frame) => {
+                        let mut profiles_frame = std::mem::take(profiles_frame);
+                        profiles_frame.submit(self).await;
+                        // Opening a profile already switched to the login frame
+                        if let AppState::Profiles(_) = self.app_state {
+                            self.app_state = AppState::Profiles(profiles_frame);
                         }
                     },
-                    _ => {}
                 }
+            },
+            _ => {}
+        }
+    }
+
+    fn handle_socket_event(&mut self, event: SocketEvent) {
+        if let AppState::Chat(ref mut chat_frame) = self.app_state {
+            let mut mem_chat = std::mem::take(chat_frame);
+            mem_chat.handle_socket_event(event, self);
+            // Logging out already switched to the login frame
+            if let AppState::Chat(_) = self.app_state {
+                self.app_state = AppState::Chat(mem_chat);
             }
         }
     }
 
-    pub fn set_socket(&mut self, socket: WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>) {
-        self.socket = Some(socket);
+    async fn reconnect_tick(&mut self) {
+        if let AppState::Chat(ref mut chat_frame) = self.app_state {
+            let mut mem_chat = std::mem::take(chat_frame);
+            mem_chat.reconnect_tick(self).await;
+            // A session the server doesn't take anymore ends at the login frame
+            if let AppState::Chat(_) = self.app_state {
+                self.app_state = AppState::Chat(mem_chat);
+            }
+        }
     }
 
-    pub fn change_state(&mut self, state: AppState) {
-        self.app_state = state;
+    // Hands a logged in socket to the reader and writer tasks
+    pub fn attach(&mut self, socket: Socket) {
+        self.connection = Some(Connection::spawn(socket));
     }
 
-    async fn connection(&mut self) {
-            match &mut self.app_state {
-                AppState::Chat(ref mut chat_frame) => {
-                    let mut mem_chat = std::mem::take(chat_frame);
-                    mem_chat.receive_message(self).await;
-                },
-                _ => {}
+    pub fn send(&self, frame: &ClientFrame) -> std::result::Result<(), String> {
+        self.connection.as_ref().ok_or("Not connected")?.send(frame)
+    }
+
+    // Login screen for a saved profile, or for the defaults from the config without one
+    pub fn open_login(&mut self, profile: Option<Profile>) {
+        let (server, username) = match &profile {
+            Some(profile) => (profile.server_options(), profile.username.clone()),
+            None => (self.config.server_options(), self.config.username.clone()),
+        };
+        self.profile = profile.map(|profile| profile.name);
+        let mut login_frame = LoginFrame::new(server.url.clone(), username, self.config.ui.show_password);
+        login_frame.remember = session_store::exists(self.profile.as_deref());
+        self.app_state = AppState::Login(login_frame);
+        self.server = server;
+    }
+
+    // Resumes the remembered session of the profile, the login screen is shown if there is none
+    // or the server doesn't take it anymore
+    pub async fn open_profile(&mut self, profile: Option<Profile>) {
+        self.open_login(profile);
+        let Some(remembered) = session_store::load(self.profile.as_deref()) else {
+            return;
+        };
+        // The token is only resumed on the server it came from and for the user about to log in,
+        // an empty username means nobody in particular was asked for
+        let username = match &self.app_state {
+            AppState::Login(login_frame) => login_frame.username.as_str(),
+            _ => "",
+        };
+        if remembered.server != self.server.url || (!username.is_empty() && remembered.username != username) {
+            return;
+        }
+        if let Err(err) = self.resume_remembered(remembered).await {
+            if let AppState::Login(ref mut login_frame) = self.app_state {
+                login_frame.error_message = Some(format!("Couldn't resume your saved session => {err}"));
             }
         }
+    }
+
+    async fn resume_remembered(&mut self, remembered: RememberedSession) -> std::result::Result<(), String> {
+        let (mut socket, username) = connection::resume(&self.server, &remembered.session.token).await?;
+        let mut chat_frame = ChatFrame::new(username.clone());
+        if let Ok(ServerFrame::History { messages, .. }) = connection::read_frame(&mut socket).await {
+            chat_frame.messages = messages;
+        }
+        self.attach(socket);
+        self.username = username;
+        self.session = Some(remembered.session);
+        self.change_state(AppState::Chat(chat_frame));
+        Ok(())
+    }
+
+    // The server revoked the session, so the remembered token is worthless now
+    pub fn log_out(&mut self, message: &str) {
+        session_store::delete(self.profile.as_deref());
+        self.connection = None;
+        self.reconnect = None;
+        self.session = None;
+        self.username.clear();
+        self.open_login(self.profile.as_deref().and_then(|name| self.config.profile(name)).cloned());
+        if let AppState::Login(ref mut login_frame) = self.app_state {
+            login_frame.error_message = Some(message.to_string());
+        }
+    }
+
+    pub fn open_profiles(&mut self) {
+        self.app_state = AppState::Profiles(ProfilesFrame::new(self.config.profiles.clone(), self.profile.as_deref()));
+    }
+
+    pub fn change_state(&mut self, state: AppState) {
+        self.app_state = state;
+    }
+}
+
+// Never resolves while there is nothing to reconnect
+async fn next_reconnect_tick(reconnect: &Option<Reconnect>) {
+    match reconnect {
+        Some(reconnect) => reconnect.tick().await,
+        None => future::pending().await,
+    }
+}
+
+// Never resolves while there is no connection, so the select in `run` only waits for the terminal
+async fn next_socket_event(connection: &mut Option<Connection>) -> Option<SocketEvent> {
+    match connection {
+        Some(connection) => connection.next_event().await,
+        None => future::pending().await,
+    }
 }
 
 pub enum AppState {
     Login(LoginFrame),
     Register(RegisterFrame),
     Chat(ChatFrame),
+    Profiles(ProfilesFrame),
 }
\ No newline at end of file
@@ -0,0 +1,258 @@
+use std::{env, fmt, fs, io, path::{Path, PathBuf}};
+
+use clap::Parser;
+use ratatui::style::Color;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use toml_edit::DocumentMut;
+use url::Url;
+
+#[derive(Parser)]
+#[command(version, about = "Terminal client for the WebSocket chat")]
+pub struct Args {
+    /// Path to the TOML config file, defaults to terminal-chat/config.toml in the XDG config dir
+    #[arg(short, long, env = "CHAT_CLIENT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Server to connect to, ws:// or wss://
+    #[arg(long, env = "CHAT_SERVER")]
+    pub server: Option<String>,
+
+    /// Saved profile to log in with, skips the profile picker
+    #[arg(short, long, env = "CHAT_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Username filled in on the login screen
+    #[arg(long, env = "CHAT_USERNAME")]
+    pub username: Option<String>,
+
+    /// PEM file with extra CA certificates to trust for wss://
+    #[arg(long, env = "CHAT_CA_FILE")]
+    pub ca_file: Option<PathBuf>,
+
+    /// Accept any server certificate, only for self-signed development setups
+    #[arg(long)]
+    pub insecure: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub server: String,
+    pub username: String,
+    pub ca_file: Option<PathBuf>,
+    pub insecure: bool,
+    pub ui: UiOptions,
+    pub profiles: Vec<Profile>,
+    // File the profiles are saved to, the one loaded or the default location
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+// A server the picker offers, with everything needed to log in there
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub name: String,
+    pub server: String,
+    pub username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<PathBuf>,
+    pub insecure: bool,
+}
+
+// Only the part of the config file the picker rewrites
+#[derive(Serialize)]
+struct ProfilesSection<'a> {
+    profiles: &'a [Profile],
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiOptions {
+    // Border color of the focused field, a color name or #rrggbb
+    #[serde(deserialize_with = "parse_color")]
+    pub accent_color: Color,
+    // Whether passwords start out readable, F1 still toggles them
+    pub show_password: bool,
+}
+
+// Where and how to connect, shared by every frame that opens a socket
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    pub url: String,
+    pub ca_file: Option<PathBuf>,
+    pub insecure: bool,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Write(PathBuf, io::Error),
+    Invalid(String),
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            server: "ws://127.0.0.1:8080".to_string(),
+            username: String::new(),
+            ca_file: None,
+            insecure: false,
+            ui: UiOptions::default(),
+            profiles: Vec::new(),
+            path: None,
+        }
+    }
+}
+
+impl Default for UiOptions {
+    fn default() -> Self {
+        Self {
+            accent_color: Color::Magenta,
+            show_password: false,
+        }
+    }
+}
+
+impl ClientConfig {
+    // Defaults, then the config file, then environment variables and flags
+    pub fn load(args: &Args) -> Result<Self, ConfigError> {
+        let path = args.config.clone().or_else(default_path);
+        let mut config = match &path {
+            Some(path) if args.config.is_some() || path.exists() => Self::from_file(path)?,
+            _ => Self::default(),
+        };
+        config.path = path;
+
+        if let Some(server) = &args.server {
+            config.server = server.clone();
+        }
+        if let Some(username) = &args.username {
+            config.username = username.clone();
+        }
+        if let Some(ca_file) = &args.ca_file {
+            config.ca_file = Some(ca_file.clone());
+        }
+        if args.insecure {
+            config.insecure = true;
+        }
+
+        validate_server(&config.server).map_err(ConfigError::Invalid)?;
+        for (index, profile) in config.profiles.iter().enumerate() {
+            config.validate_profile(profile, Some(index))
+                .map_err(|err| ConfigError::Invalid(format!("profile '{}': {}", profile.name, err)))?;
+        }
+        if let Some(name) = &args.profile {
+            if config.profile(name).is_none() {
+                return Err(ConfigError::Invalid(format!("no profile named '{name}'")));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    // `index` is the profile being replaced, it may keep its own name
+    pub fn validate_profile(&self, profile: &Profile, index: Option<usize>) -> Result<(), String> {
+        if profile.name.trim().is_empty() {
+            return Err("Profile name cannot be empty".to_string());
+        }
+        let taken = self.profiles.iter().enumerate()
+            .any(|(other, existing)| Some(other) != index && existing.name == profile.name);
+        if taken {
+            return Err(format!("There already is a profile named '{}'", profile.name));
+        }
+        validate_server(&profile.server)
+    }
+
+    // Rewrites only the profiles of the config file, comments and everything else stay as they are
+    pub fn save_profiles(&self) -> Result<(), ConfigError> {
+        let path = self.path.as_ref()
+            .ok_or_else(|| ConfigError::Invalid("no config dir to save profiles to, pass --config".to_string()))?;
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(ConfigError::Read(path.clone(), err)),
+        };
+        let mut document = content.parse::<DocumentMut>()
+            .map_err(|err| ConfigError::Invalid(format!("{} is no longer valid TOML: {}", path.display(), err)))?;
+
+        if self.profiles.is_empty() {
+            document.remove("profiles");
+        } else {
+            let section = toml::to_string(&ProfilesSection { profiles: &self.profiles }).expect("Profiles are always serializable");
+            let section = section.parse::<DocumentMut>().expect("Serialized profiles are valid TOML");
+            document["profiles"] = section["profiles"].clone();
+            // A blank line in front of every profile, except at the very top of a new file
+            if let Some(profiles) = document["profiles"].as_array_of_tables_mut() {
+                for profile in profiles.iter_mut().skip(usize::from(content.trim().is_empty())) {
+                    profile.decor_mut().set_prefix("\n");
+                }
+            }
+        }
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|err| ConfigError::Write(path.clone(), err))?;
+        }
+        fs::write(path, document.to_string()).map_err(|err| ConfigError::Write(path.clone(), err))
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_path_buf(), err))?;
+        toml::from_str(&content).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    pub fn server_options(&self) -> ServerOptions {
+        ServerOptions {
+            url: self.server.clone(),
+            ca_file: self.ca_file.clone(),
+            insecure: self.insecure,
+        }
+    }
+}
+
+impl Profile {
+    pub fn server_options(&self) -> ServerOptions {
+        ServerOptions {
+            url: self.server.clone(),
+            ca_file: self.ca_file.clone(),
+            insecure: self.insecure,
+        }
+    }
+}
+
+// $XDG_CONFIG_HOME/terminal-chat/config.toml, falling back to ~/.config like the spec says
+fn default_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("terminal-chat").join("config.toml"))
+}
+
+fn validate_server(server: &str) -> Result<(), String> {
+    let url = Url::parse(server).map_err(|err| format!("Invalid server URL '{server}' => {err}"))?;
+    match url.scheme() {
+        "ws" | "wss" => Ok(()),
+        scheme => Err(format!("Unsupported scheme '{scheme}', use ws:// or wss://")),
+    }
+}
+
+fn parse_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    name.parse().map_err(|_| D::Error::custom(format!("unknown color '{name}'")))
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "Failed to read config file {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "Failed to parse config file {}: {}", path.display(), err),
+            ConfigError::Write(path, err) => write!(f, "Failed to write config file {}: {}", path.display(), err),
+            ConfigError::Invalid(message) => write!(f, "Invalid configuration: {}", message),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::{fmt, fs::File, io::BufReader, sync::Arc, time::Duration};
+
+use chat_protocol::{features, is_supported_version, ClientFrame, ErrorCode, ServerFrame, PROTOCOL_VERSION};
+use futures_util::{SinkExt, StreamExt};
+use rustls::{client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier}, crypto::{ring, CryptoProvider}, pki_types::{CertificateDer, ServerName, UnixTime}, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio::{net::TcpStream, sync::mpsc::{self, UnboundedReceiver, UnboundedSender}};
+use tokio_tungstenite::{connect_async_tls_with_config, tungstenite::Message, Connector, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+use crate::config::ServerOptions;
+
+pub type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// We ping the server this often, its pongs and pings tell us the connection is still alive
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+// Without anything from the server for this long the connection is considered dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(45);
+// Connecting, the protocol handshake and every answer read before the chat opens each get this long, the UI waits for them
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const FEATURES: &[&str] = &[features::CHAT, features::HISTORY, features::HISTORY_PAGES, features::ROOMS, features::DIRECT_MESSAGES, features::SESSIONS];
+
+// Opens a socket to the server and performs the protocol handshake
+pub async fn connect(server: &ServerOptions) -> Result<Socket, String> {
+    // A server that drops our packets would otherwise hang until the OS gives up
+    tokio::time::timeout(CONNECT_TIMEOUT, open(server)).await
+        .map_err(|_| format!("Timed out connecting to {}", server.url))?
+}
+
+async fn open(server: &ServerOptions) -> Result<Socket, String> {
+    let url = Url::parse(&server.url).map_err(|err| format!("Invalid server URL {} => {err}", server.url))?;
+    let connector = match url.scheme() {
+        "ws" => Connector::Plain,
+        "wss" => Connector::Rustls(Arc::new(tls_config(server)?)),
+        scheme => return Err(format!("Unsupported scheme {scheme}://, use ws:// or wss://")),
+    };
+    let (mut socket, _) = match connect_async_tls_with_config(url.as_str(), None, false, Some(connector)).await {
+        Ok(result) => result,
+        Err(e) => return Err(format!("Failed to connect: {}", e)),
+    };
+
+    let hello = ClientFrame::Hello {
+        version: PROTOCOL_VERSION,
+        features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+    };
+    if let Err(e) = socket.send(Message::Text(hello.encode())).await {
+        return Err(format!("Failed to send handshake: {}", e));
+    }
+
+    match socket.next().await {
+        Some(Ok(Message::Text(response))) => match ServerFrame::decode(&response) {
+            Ok(ServerFrame::Welcome { version, .. }) if is_supported_version(version) => Ok(socket),
+            Ok(ServerFrame::Welcome { version, .. }) => Err(format!(
+                "Server speaks protocol v{version} which this client (v{PROTOCOL_VERSION}) does not support. Please upgrade terminal-chat"
+            )),
+            Ok(ServerFrame::Incompatible { message, .. }) => Err(message),
+            _ => Err("Failed to establish connection, the server may be outdated".to_string()),
+        },
+        _ => Err("Failed to receive connection confirmation".to_string()),
+    }
+}
+
+// Why a session couldn't be resumed
+#[derive(Debug)]
+pub enum ResumeError {
+    // The server doesn't take the token anymore, only logging in again helps
+    InvalidSession(String),
+    // Anything else, worth trying again later
+    Failed(String),
+}
+
+// Opens a new socket and authenticates it with the token of an earlier login, returns the username.
+// The server follows up with the history of the default room like after a login
+pub async fn resume(server: &ServerOptions, token: &str) -> Result<(Socket, String), ResumeError> {
+    let mut socket = connect(server).await.map_err(ResumeError::Failed)?;
+    let resume = ClientFrame::Resume { token: token.to_string() };
+    if let Err(e) = socket.send(Message::Text(resume.encode())).await {
+        return Err(ResumeError::Failed(format!("Failed to send message => {e}")));
+    }
+
+    match read_frame(&mut socket).await.map_err(ResumeError::Failed)? {
+        ServerFrame::Resumed { username } => Ok((socket, username)),
+        ServerFrame::Error { code: ErrorCode::InvalidSession, message } => Err(ResumeError::InvalidSession(message)),
+        ServerFrame::Error { message, .. } => Err(ResumeError::Failed(message)),
+        _ => Err(ResumeError::Failed("Unexpected response from server".to_string())),
+    }
+}
+
+impl fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResumeError::InvalidSession(message) | ResumeError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<ResumeError> for String {
+    fn from(err: ResumeError) -> Self {
+        err.to_string()
+    }
+}
+
+// What the reader task hands to the UI
+pub enum SocketEvent {
+    Frame(ServerFrame),
+    Invalid(String),
+    // The socket is gone, with the reason the server gave if it closed it
+    Closed(Option<String>),
+}
+
+// A logged in socket split into a reader and a writer task, so frames from the server
+// show up as they arrive instead of only as the answer to something we sent
+pub struct Connection {
+    outgoing: UnboundedSender<Message>,
+    events: UnboundedReceiver<SocketEvent>,
+}
+
+impl Connection {
+    pub fn spawn(socket: Socket) -> Self {
+        let (mut write, mut read) = socket.split();
+        let (outgoing, mut outgoing_receiver) = mpsc::unbounded_channel::<Message>();
+        let (events_sender, events) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + PING_INTERVAL, PING_INTERVAL);
+            loop {
+                let message = tokio::select! {
+                    message = outgoing_receiver.recv() => match message {
+                        Some(message) => message,
+                        None => break,
+                    },
+                    _ = ping.tick() => Message::Ping(Vec::new()),
+                };
+                if write.send(message).await.is_err() {
+                    break;
+                }
+            }
+            // The connection was dropped or the socket failed
+            let _ = write.close().await;
+        });
+
+        tokio::spawn(async move {
+            // Reading is also what answers the server's pings
+            let reason = loop {
+                let Ok(message) = tokio::time::timeout(READ_TIMEOUT, read.next()).await else {
+                    break None;
+                };
+                let event = match message {
+                    Some(Ok(Message::Text(text))) => match ServerFrame::decode(&text) {
+                        Ok(frame) => SocketEvent::Frame(frame),
+                        Err(err) => SocketEvent::Invalid(format!("Invalid response from server => {err}")),
+                    },
+                    Some(Ok(Message::Close(frame))) => break frame.map(|frame| frame.reason.to_string()),
+                    Some(Ok(_)) => continue,
+                    Some(Err(_)) | None => break None,
+                };
+                if events_sender.send(event).is_err() {
+                    return;
+                }
+            };
+            let _ = events_sender.send(SocketEvent::Closed(reason));
+        });
+
+        Self { outgoing, events }
+    }
+
+    pub fn send(&self, frame: &ClientFrame) -> Result<(), String> {
+        self.outgoing.send(Message::Text(frame.encode())).map_err(|_| "Not connected".to_string())
+    }
+
+    pub async fn next_event(&mut self) -> Option<SocketEvent> {
+        self.events.recv().await
+    }
+}
+
+// Reads the answer to something sent before the socket is handed to a `Connection`
+pub async fn read_frame(socket: &mut Socket) -> Result<ServerFrame, String> {
+    let message = tokio::time::timeout(CONNECT_TIMEOUT, socket.next()).await
+        .map_err(|_| "Timed out waiting for the server".to_string())?;
+    match message {
+        Some(Ok(Message::Text(response))) => ServerFrame::decode(&response).map_err(|err| format!("Invalid response from server => {err}")),
+        Some(Ok(_)) => Err("Unexpected message from server".to_string()),
+        Some(Err(err)) => Err(format!("Failed to receive message => {err}")),
+        None => Err("Connection closed by server".to_string()),
+    }
+}
+
+// System roots plus the configured CA file, or no verification at all with --insecure
+fn tls_config(server: &ServerOptions) -> Result<ClientConfig, String> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?;
+
+    if server.insecure {
+        return Ok(builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+            .with_no_client_auth());
+    }
+
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    if let Some(ca_file) = &server.ca_file {
+        let file = File::open(ca_file).map_err(|err| format!("Failed to open {} => {err}", ca_file.display()))?;
+        for cert in rustls_pemfile::certs(&mut BufReader::new(file)) {
+            let cert = cert.map_err(|err| format!("Failed to read {} => {err}", ca_file.display()))?;
+            roots.add(cert).map_err(|err| format!("Invalid CA certificate in {} => {err}", ca_file.display()))?;
+        }
+    }
+    if roots.is_empty() {
+        return Err("No trusted CA certificates found, pass --ca-file".to_string());
+    }
+
+    Ok(builder.with_root_certificates(roots).with_no_client_auth())
+}
+
+// Accepts every certificate but still checks the handshake signatures
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(&self, _: &CertificateDer<'_>, _: &[CertificateDer<'_>], _: &ServerName<'_>, _: &[u8], _: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
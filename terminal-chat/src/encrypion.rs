@@ -1,8 +1,9 @@
-use sha2::{Sha256, Digest};
+use sha2::{Digest, Sha256};
 
-pub fn encrypt_password(password: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(password);
-    let result = hasher.finalize();
-    format!("{:x}", result)
+// The challenge-response login itself lives in chat_protocol::scram
+
+// What clients from before challenge-response login sent instead of the password,
+// only used once to migrate such an account
+pub fn legacy_digest(password: &str) -> String {
+    format!("{:x}", Sha256::digest(password.as_bytes()))
 }
@@ -1,24 +1,57 @@
+use std::{cell::Cell, collections::{HashMap, HashSet}, time::Duration};
 
-use futures_util::{SinkExt, StreamExt};
-use ratatui::{layout::{self, Alignment}, style::{Color, Style, Stylize}, widgets::{Block, Borders, Paragraph}, Frame};
-use tokio_tungstenite::tungstenite::Message;
+use ratatui::{layout::{self, Alignment}, style::{Color, Style, Stylize}, text::Line, widgets::{Block, Borders, Paragraph}, Frame};
 
-use crate::{app::App, frames::custom_frame::CustomFrame};
+use chat_protocol::{ChatMessage, ClientFrame, HistoryCursor, ServerFrame, DEFAULT_ROOM};
 
-#[derive(Debug, Clone)]
+use crate::{app::App, config::UiOptions, connection::{self, ResumeError, SocketEvent}, frames::custom_frame::CustomFrame, reconnect::Reconnect};
+
+const HISTORY_PAGE_SIZE: u32 = 50;
+
+#[derive(Debug, Clone, Default)]
 pub struct ChatFrame {
-    pub messages: Vec<String>,
+    pub username: String,
+    // Messages of every joined room, only the current room is shown.
+    // Direct messages live in "@<user>" rooms that only exist on the client
+    pub messages: Vec<ChatMessage>,
     pub input: String,
     pub focus: bool,
+    pub room: String,
+    pub rooms: Vec<String>,
+    pub status: Option<String>,
+    // Lines scrolled up from the newest message
+    pub scroll: u16,
+    // Rooms with no older messages left on the server
+    pub exhausted_rooms: HashSet<String>,
+    // A history page was asked for and hasn't arrived yet
+    loading_history: bool,
+    // Sent `Logout` and waiting for the server to confirm it
+    logging_out: bool,
+    // How long the server asked us to wait before reconnecting when it announced a shutdown
+    reconnect_hint: Option<Duration>,
+    // Newest message of each room from before a reconnect, history is paged in until it is reached again
+    catching_up: HashMap<String, HistoryCursor>,
+    visible_lines: Cell<u16>,
 }
 
 impl ChatFrame {
 
-    pub fn new() -> Self {
+    pub fn new(username: String) -> Self {
         Self {
+            username,
             messages: Vec::new(),
             input: String::new(),
             focus: false,
+            room: DEFAULT_ROOM.to_string(),
+            rooms: vec![DEFAULT_ROOM.to_string()],
+            status: None,
+            scroll: 0,
+            exhausted_rooms: HashSet::new(),
+            loading_history: false,
+            logging_out: false,
+            reconnect_hint: None,
+            catching_up: HashMap::new(),
+            visible_lines: Cell::new(0),
         }
     }
 
@@ -31,32 +64,62 @@ impl ChatFrame {
     }
 
     pub async fn submit_message(&mut self, app: &mut App) {
-        let username = app.username.clone();
         let input_message = self.input.clone();
-    
-        match self.submit_message_to_server(username, input_message, app).await {
+        self.status = None;
+
+        let result = match input_message.strip_prefix('/') {
+            Some(command) => self.run_command(command, app).await,
+            None => self.submit_message_to_server(input_message, app).await,
+        };
+        match result {
             Ok(_) => {
-                
+                self.input.clear();
             },
             Err(err) => {
-                eprintln!("Error submitting message: {err}");
+                self.status = Some(err);
+            },
+        }
+    }
+
+    // Everything the server sends is applied here as it arrives, including the answers to our own requests
+    pub fn handle_socket_event(&mut self, event: SocketEvent, app: &mut App) {
+        match event {
+            // Whatever the server answers, our session is gone once it closes the connection
+            SocketEvent::Frame(ServerFrame::SessionRevoked { .. }) | SocketEvent::Closed(_) if self.logging_out => app.log_out("Logged out"),
+            SocketEvent::Frame(frame) => self.apply_frame(frame, app),
+            SocketEvent::Invalid(err) => self.status = Some(err),
+            SocketEvent::Closed(reason) => {
+                app.connection = None;
+                let reason = match reason {
+                    Some(reason) if !reason.is_empty() => format!("Disconnected by server => {reason}"),
+                    _ => "Connection to the server lost".to_string(),
+                };
+                let reconnect = Reconnect::start(reason, self.reconnect_hint.take());
+                self.status = Some(reconnect.status());
+                app.reconnect = Some(reconnect);
             },
         }
     }
 
-    //TODO: Implement receive_message in a loop
-    pub async fn receive_message(&mut self, app: &mut App) {
-        if app.socket.is_none() {
+    // Counts down to the next attempt to resume the session and makes it once it is due
+    pub async fn reconnect_tick(&mut self, app: &mut App) {
+        let Some(reconnect) = app.reconnect.take() else {
+            return;
+        };
+        if !reconnect.is_due() {
+            self.status = Some(reconnect.status());
+            app.reconnect = Some(reconnect);
             return;
         }
 
-        while let Some(Ok(Message::Text(res))) = app.socket.as_mut().unwrap().next().await {
-            let response: Vec<&str> = res.split(':').collect();
-            if response.len() == 3 && response[0] == "msg" {
-                self.messages.push(format!("{} > {}", response[1], response[2]));
-            } else {
-                eprintln!("Invalid response format");
-            }
+        match self.resume(app).await {
+            Ok(_) => self.status = Some("Reconnected".to_string()),
+            Err(ResumeError::InvalidSession(message)) => app.log_out(&message),
+            Err(ResumeError::Failed(err)) => {
+                let reconnect = reconnect.retry(format!("Reconnecting failed => {err}"));
+                self.status = Some(reconnect.status());
+                app.reconnect = Some(reconnect);
+            },
         }
     }
 
@@ -64,30 +127,271 @@ impl ChatFrame {
         self.focus = !self.focus;
     }
 
-    async fn submit_message_to_server(&self, username: String, message: String, app: &mut App) -> Result<String, String> {
-        let socket = app.socket.as_mut().unwrap();
-    
-        match socket.send(Message::Text(format!("msg:{}:{}", username, message))).await {
-            Ok(_) => {
-                if let Some(Ok(Message::Text(res))) = socket.next().await {
-                    let response: Vec<&str> = res.split(':').collect();
-                    if response.len() == 3 && response[0] == "msg" {
-                        return Ok("Message sent".to_string());
-                    } else {
-                        return Err("Invalid response format".to_string());
+    // Returns true when already at the top of the loaded messages
+    pub fn scroll_up(&mut self) -> bool {
+        let max_scroll = (self.room_messages().count() as u16).saturating_sub(self.visible_lines.get());
+        if self.scroll < max_scroll {
+            self.scroll += 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+
+    pub async fn load_older_messages(&mut self, app: &mut App) {
+        if let Err(err) = self.fetch_history_page(app).await {
+            self.status = Some(err);
+        }
+    }
+
+    // The other user when the current room is a direct conversation
+    fn direct_peer(&self) -> Option<&str> {
+        self.room.strip_prefix('@')
+    }
+
+    fn room_messages(&self) -> impl Iterator<Item = &ChatMessage> {
+        self.messages.iter().filter(|message| message.room == self.room)
+    }
+
+    async fn fetch_history_page(&mut self, app: &mut App) -> Result<(), String> {
+        if self.loading_history || self.exhausted_rooms.contains(&self.room) || self.direct_peer().is_some() {
+            return Ok(());
+        }
+        let Some(oldest) = self.room_messages().next() else {
+            self.exhausted_rooms.insert(self.room.clone());
+            return Ok(());
+        };
+        let request = ClientFrame::FetchHistory {
+            room: self.room.clone(),
+            before: HistoryCursor::from(oldest),
+            limit: HISTORY_PAGE_SIZE,
+        };
+
+        self.request(app, request).await?;
+        self.loading_history = true;
+        Ok(())
+    }
+
+    async fn run_command(&mut self, command: &str, app: &mut App) -> Result<(), String> {
+        let (name, argument) = match command.split_once(' ') {
+            Some((name, argument)) => (name, argument.trim().to_string()),
+            None => (command, String::new()),
+        };
+
+        match name {
+            "join" if !argument.is_empty() => self.request(app, ClientFrame::JoinRoom { name: argument }).await,
+            "create" if !argument.is_empty() => self.request(app, ClientFrame::CreateRoom { name: argument }).await,
+            "msg" => match argument.split_once(' ') {
+                Some((to, body)) => {
+                    let to = to.to_string();
+                    self.request(app, ClientFrame::Direct { to: to.clone(), body: body.to_string() }).await?;
+                    self.room = format!("@{to}");
+                    self.scroll = 0;
+                    Ok(())
+                },
+                None => Err("Usage: /msg <user> <message>".to_string()),
+            },
+            "leave" => {
+                let name = if argument.is_empty() { self.room.clone() } else { argument };
+                // Direct conversations are only known to the client
+                if name.starts_with('@') {
+                    self.apply_frame(ServerFrame::RoomLeft { name }, app);
+                    return Ok(());
+                }
+                self.request(app, ClientFrame::LeaveRoom { name }).await
+            },
+            "rooms" => self.request(app, ClientFrame::ListRooms).await,
+            "sessions" => self.request(app, ClientFrame::ListSessions).await,
+            "revoke" if !argument.is_empty() => self.request(app, ClientFrame::RevokeSession { id: argument }).await,
+            "logout" => {
+                self.request(app, ClientFrame::Logout).await?;
+                self.logging_out = true;
+                self.status = Some("Logging out...".to_string());
+                Ok(())
+            },
+            "switch" if self.rooms.contains(&argument) => {
+                self.room = argument;
+                self.scroll = 0;
+                Ok(())
+            },
+            "switch" => Err(format!("You are not in #{argument}, use /join {argument} first")),
+            _ => Err("Commands: /join <room>, /create <room>, /leave [room], /switch <room>, /rooms, /msg <user> <message>, /sessions, /revoke <session>, /logout".to_string()),
+        }
+    }
+
+    // Sends a frame, whatever the server answers is applied by `handle_socket_event` once it arrives.
+    // A dropped connection is resumed with the session token once and the frame sent again
+    async fn request(&mut self, app: &mut App, frame: ClientFrame) -> Result<(), String> {
+        match app.send(&frame) {
+            Ok(_) => Ok(()),
+            Err(_) if app.session.is_some() => {
+                self.resume(app).await?;
+                app.send(&frame)
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    // Opens a new connection for the current login and joins the rooms that were open before.
+    // The server sends the history of the default room first, the other rooms follow as they are joined
+    async fn resume(&mut self, app: &mut App) -> Result<(), ResumeError> {
+        let token = app.session.as_ref()
+            .ok_or_else(|| ResumeError::InvalidSession("Not logged in".to_string()))?
+            .token.clone();
+        let (socket, _) = connection::resume(&app.server, &token).await?;
+        app.attach(socket);
+        app.reconnect = None;
+        // A page asked for on the old socket is never going to arrive
+        self.loading_history = false;
+
+        self.catching_up = self.rooms.iter()
+            .filter(|room| !room.starts_with('@'))
+            .filter_map(|room| {
+                let newest = self.messages.iter().rev().find(|message| message.room == *room)?;
+                Some((room.clone(), HistoryCursor::from(newest)))
+            })
+            .collect();
+
+        let rooms = self.rooms.iter().filter(|room| *room != DEFAULT_ROOM && !room.starts_with('@'));
+        for room in rooms {
+            app.send(&ClientFrame::JoinRoom { name: room.clone() }).map_err(ResumeError::Failed)?;
+        }
+        // The server puts every new connection into the default room, even when it was left before
+        if !self.rooms.iter().any(|room| room == DEFAULT_ROOM) {
+            app.send(&ClientFrame::LeaveRoom { name: DEFAULT_ROOM.to_string() }).map_err(ResumeError::Failed)?;
+        }
+        Ok(())
+    }
+
+    // Pages back through what was missed while disconnected until it meets the newest message from before
+    fn catch_up(&mut self, room: &str, messages: &[ChatMessage], has_more: bool, app: &App) {
+        let Some(newest) = self.catching_up.get(room) else {
+            return;
+        };
+        let reached = messages.first()
+            .is_none_or(|oldest| (oldest.timestamp, &oldest.id) <= (newest.timestamp, &newest.id));
+        if reached || !has_more {
+            self.catching_up.remove(room);
+            return;
+        }
+
+        let request = ClientFrame::FetchHistory {
+            room: room.to_string(),
+            before: HistoryCursor::from(&messages[0]),
+            limit: HISTORY_PAGE_SIZE,
+        };
+        if app.send(&request).is_err() {
+            self.catching_up.remove(room);
+        }
+    }
+
+    // Keeps every room in order with each message once, history overlaps what we already have after a reconnect
+    fn merge(&mut self, messages: Vec<ChatMessage>) {
+        let known = self.messages.iter().map(|message| message.id.clone()).collect::<HashSet<String>>();
+        self.messages.extend(messages.into_iter().filter(|message| !known.contains(&message.id)));
+        self.messages.sort_by(|a, b| (a.timestamp, &a.id).cmp(&(b.timestamp, &b.id)));
+    }
+
+    pub fn apply_frame(&mut self, frame: ServerFrame, app: &App) {
+        match frame {
+            ServerFrame::Chat(message) => self.messages.push(message),
+            ServerFrame::History { room, messages } => {
+                // Whether the room has older messages is only known once a page comes back
+                self.catch_up(&room, &messages, true, app);
+                self.merge(messages);
+            },
+            ServerFrame::HistoryPage { room, messages, has_more } => {
+                if self.catching_up.contains_key(&room) {
+                    self.catch_up(&room, &messages, has_more, app);
+                } else {
+                    self.loading_history = false;
+                    if !has_more {
+                        self.exhausted_rooms.insert(room);
                     }
                 }
+                self.merge(messages);
+            },
+            ServerFrame::RoomCreated { name } => {
+                self.status = Some(format!("Created #{name}"));
+            },
+            // Rooms rejoined after a resume are already known, the current room stays as it is
+            ServerFrame::RoomJoined { name } if !self.rooms.contains(&name) => {
+                self.rooms.push(name.clone());
+                self.status = Some(format!("Joined #{name}"));
+                self.room = name;
+                self.scroll = 0;
             },
-            Err(err) => return Err(format!("Failed to send message => {err}")),
+            ServerFrame::RoomLeft { name } => {
+                // Leaving the default room again after a resume is nothing to report
+                if self.rooms.contains(&name) {
+                    self.status = Some(format!("Left #{name}"));
+                }
+                self.rooms.retain(|room| *room != name);
+                self.messages.retain(|message| message.room != name);
+                self.exhausted_rooms.remove(&name);
+                if self.room == name {
+                    self.room = self.rooms.first().cloned().unwrap_or_default();
+                    self.scroll = 0;
+                }
+            },
+            ServerFrame::Direct(direct) => {
+                let peer = if direct.from == self.username { &direct.to } else { &direct.from };
+                let room = format!("@{peer}");
+                if !self.rooms.contains(&room) {
+                    self.rooms.push(room.clone());
+                }
+                self.messages.push(ChatMessage {
+                    id: direct.id,
+                    sender: direct.from,
+                    body: direct.body,
+                    timestamp: direct.timestamp,
+                    room,
+                });
+            },
+            ServerFrame::RoomList { rooms } => {
+                let rooms = rooms.iter().map(|room| format!("#{} ({} online)", room.name, room.online)).collect::<Vec<String>>();
+                self.status = Some(format!("Rooms: {}", rooms.join(", ")));
+            },
+            ServerFrame::SessionList { sessions } => {
+                let sessions = sessions.iter()
+                    .map(|session| if session.current { format!("{} (this one)", session.id) } else { session.id.clone() })
+                    .collect::<Vec<String>>();
+                self.status = Some(format!("Sessions: {}", sessions.join(", ")));
+            },
+            ServerFrame::SessionRevoked { id } => {
+                self.status = Some(format!("Revoked session {id}"));
+            },
+            ServerFrame::ShuttingDown { message, reconnect_after_ms } => {
+                self.reconnect_hint = Some(Duration::from_millis(reconnect_after_ms));
+                self.status = Some(format!("{message}, try again in {}s", reconnect_after_ms.div_ceil(1000)));
+            },
+            ServerFrame::Error { message, .. } => {
+                // Can't tell which request failed, so a history page may never come
+                self.loading_history = false;
+                self.logging_out = false;
+                self.status = Some(message);
+            },
+            _ => {}
         }
-    
-        Err("Failed to send message".to_string())
     }
-    
+
+    async fn submit_message_to_server(&mut self, message: String, app: &mut App) -> Result<(), String> {
+        if let Some(peer) = self.direct_peer() {
+            let direct = ClientFrame::Direct { to: peer.to_string(), body: message };
+            return self.request(app, direct).await;
+        }
+        let chat = ClientFrame::Chat { room: self.room.clone(), body: message };
+        self.request(app, chat).await
+    }
+
 }
 
 impl CustomFrame for ChatFrame {
-    fn render(&self, frame: &mut Frame) {
+    fn render(&self, frame: &mut Frame, ui: &UiOptions) {
         let layout = layout::Layout::default()
             .direction(layout::Direction::Vertical)
             .constraints(
@@ -100,23 +404,29 @@ impl CustomFrame for ChatFrame {
             )
             .split(frame.area());
 
+        let rooms = self.rooms.iter().map(|room| format!("#{room}")).collect::<Vec<String>>().join(" ");
         let messages_block = Block::default()
             .borders(Borders::ALL)
-            .title("Messages")
-            .border_style(Style::default().fg(if self.focus { Color::Magenta } else { Color::Reset }));
+            .title(format!("Messages - #{}", self.room))
+            .title_bottom(Line::from(self.status.clone().unwrap_or(rooms)).right_aligned())
+            .border_style(Style::default().fg(if self.focus { ui.accent_color } else { Color::Reset }));
 
         let input_block = Block::default()
             .borders(Borders::ALL)
             .title("Input")
-            .border_style(if !self.focus { Color::Magenta } else { Color::Reset });
+            .border_style(if !self.focus { ui.accent_color } else { Color::Reset });
 
         let input_paragraph = Paragraph::new(self.input.as_str()).block(input_block);
 
-        let messages = self.messages.iter().map(|msg| format!("{}", msg)).collect::<Vec<String>>();
+        let messages = self.room_messages().map(|msg| format!("{} > {}", msg.sender, msg.body)).collect::<Vec<String>>();
+        let messages_count = messages.len() as u16;
         let messages = messages.join("\n");
-        let messages_paragraph = Paragraph::new(messages.as_str()).block(messages_block).fg(Color::Yellow);
+        let visible_lines = layout[0].height.saturating_sub(2);
+        self.visible_lines.set(visible_lines);
+        let top = messages_count.saturating_sub(visible_lines).saturating_sub(self.scroll);
+        let messages_paragraph = Paragraph::new(messages.as_str()).block(messages_block).fg(Color::Yellow).scroll((top, 0));
 
-        let help_paragraph = Paragraph::new("Press 'Tab' to change focus | Press 'Enter' to submit message | Press 'Backspace' to delete last character | Press 'Esc' to exit")
+        let help_paragraph = Paragraph::new("Press 'Tab' to change focus | Press 'Up'/'Down' to scroll messages | Press 'Enter' to submit message or /command | Press 'Esc' to exit")
         .alignment(Alignment::Center)
         .style(Style::default().fg(Color::Yellow));
 
@@ -126,13 +436,3 @@ impl CustomFrame for ChatFrame {
     }
 
 }
-
-impl Default for ChatFrame {
-    fn default() -> Self {
-        Self {
-            messages: Vec::new(),
-            input: String::new(),
-            focus: false,
-        }
-    }
-}
\ No newline at end of file
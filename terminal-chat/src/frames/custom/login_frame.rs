@@ -1,85 +1,149 @@
-use std::iter::repeat;
-
 use crossterm::style::Color;
-use futures_util::{SinkExt, StreamExt};
+use futures_util::SinkExt;
 use ratatui::{layout::{self, Alignment, Constraint, Layout, Rect}, style::Stylize, widgets::{Block, Borders, Paragraph}, Frame};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use url::Url;
+use tokio_tungstenite::tungstenite::Message;
+
+use chat_protocol::{scram::{self, KdfParams}, ClientFrame, ErrorCode, ServerFrame, SessionToken};
 
-use crate::{app::{App, AppState}, encrypion, frames::custom_frame::CustomFrame};
+use crate::{app::{App, AppState}, config::UiOptions, connection::{self, Socket}, encrypion, frames::custom_frame::CustomFrame, session_store::{self, RememberedSession}};
 
 use super::chat_frame::ChatFrame;
 
 pub struct LoginFrame {
+    pub server: String,
     pub username: String,
     pub password: String,
-    pub focus: bool,
     pub password_visible: bool,
+    // Keep the session token on disk so the next start skips the login
+    pub remember: bool,
     pub error_message: Option<String>,
+    focus: Focus,
 }
 
 impl LoginFrame {
 
-    pub fn new() -> Self {
+    // Prefilled from the config, a known username puts the cursor straight into the password field
+    pub fn new(server: String, username: String, password_visible: bool) -> Self {
+        let focus = if username.is_empty() { Focus::Username } else { Focus::Password };
         Self {
-            username: String::new(),
+            server,
+            username,
             password: String::new(),
-            focus: true,
-            password_visible: false,
+            password_visible,
+            remember: false,
             error_message: None,
+            focus,
         }
     }
 
     pub fn input(&mut self, c: char) {
-        if self.focus {
-            self.username.push(c);
-        } else {
-            self.password.push(c);
+        match self.focus {
+            Focus::Server => self.server.push(c),
+            Focus::Username => self.username.push(c),
+            Focus::Password => self.password.push(c),
+            // Any key flips the checkbox
+            Focus::Remember => self.remember = !self.remember,
         }
     }
 
     pub fn backspace(&mut self) {
-        if self.focus {
-            self.username.pop();
-        } else {
-            self.password.pop();
+        match self.focus {
+            Focus::Server => { self.server.pop(); },
+            Focus::Username => { self.username.pop(); },
+            Focus::Password => { self.password.pop(); },
+            Focus::Remember => self.remember = false,
         }
     }
 
+    pub fn change_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::Server => Focus::Username,
+            Focus::Username => Focus::Password,
+            Focus::Password => Focus::Remember,
+            Focus::Remember => Focus::Server,
+        };
+    }
+
     pub async fn submit(&self, app: &mut App) -> std::result::Result<(), String> {
         if self.username.is_empty() || self.password.is_empty() {
             return Err("Username and Password cannot be empty".to_string());
         }
+        if self.server.trim().is_empty() {
+            return Err("Server cannot be empty".to_string());
+        }
 
-        let url = Url::parse("ws://127.0.0.1:8080").unwrap();
-        let (mut socket, _) = match connect_async(url.as_str()).await {
-            Ok(result) => result,
-            Err(e) => return Err(format!("Failed to connect: {}", e)),
+        app.server.url = self.server.trim().to_string();
+        let mut socket = connection::connect(&app.server).await?;
+        let client_nonce = scram::nonce();
+        let login = ClientFrame::Login { username: self.username.clone(), nonce: client_nonce.clone() };
+        socket.send(Message::Text(login.encode())).await.map_err(|err| format!("Failed to send message => {err}"))?;
+
+        let login_proof = match connection::read_frame(&mut socket).await? {
+            ServerFrame::LoginChallenge { nonce, salt, params } => {
+                let login_proof = scram::login_proof(&self.username, &self.password, &client_nonce, &nonce, &salt, params)?;
+                socket.send(Message::Text(ClientFrame::LoginProof { proof: login_proof.proof.clone() }.encode()))
+                .await.map_err(|err| format!("Failed to send message => {err}"))?;
+                login_proof
+            },
+            ServerFrame::Error { code: ErrorCode::AccountOutdated, .. } => return self.migrate(app, socket).await,
+            ServerFrame::Error { message, .. } => return Err(message),
+            _ => return Err("Unexpected response from server".to_string()),
         };
 
-        if let Some(Ok(Message::Text(response))) = socket.next().await {
-            if response != "connection:success" {
-            return Err("Failed to establish connection".to_string());
-            }
-        } else {
-            return Err("Failed to receive connection confirmation".to_string());
+        match connection::read_frame(&mut socket).await? {
+            ServerFrame::LoggedIn { username, signature, session, rehash } => {
+                // Only a server holding our credentials can produce this signature
+                if signature != login_proof.server_signature {
+                    return Err("Server failed to prove it knows your credentials".to_string());
+                }
+                // The server asks for stronger credentials, its answer is picked up by the chat frame
+                if let Some(params) = rehash {
+                    let credentials = scram::derive_credentials(&self.password, params)?;
+                    socket.send(Message::Text(ClientFrame::UpdateCredentials { credentials }.encode()))
+                    .await.map_err(|err| format!("Failed to send message => {err}"))?;
+                }
+                self.enter_chat(app, socket, username, session).await;
+                Ok(())
+            },
+            ServerFrame::Error { message, .. } => Err(message),
+            _ => Err("Unexpected response from server".to_string()),
+        }
+    }
+
+    // The account is from before challenge-response login, log in the old way once and hand
+    // the server credentials to use from now on
+    async fn migrate(&self, app: &mut App, mut socket: Socket) -> std::result::Result<(), String> {
+        let credentials = scram::derive_credentials(&self.password, KdfParams::RECOMMENDED)?;
+        let login = ClientFrame::LegacyLogin { username: self.username.clone(), password: encrypion::legacy_digest(&self.password), credentials };
+        socket.send(Message::Text(login.encode())).await.map_err(|err| format!("Failed to send message => {err}"))?;
+
+        match connection::read_frame(&mut socket).await? {
+            ServerFrame::Migrated { username, session } => {
+                self.enter_chat(app, socket, username, session).await;
+                Ok(())
+            },
+            ServerFrame::Error { message, .. } => Err(message),
+            _ => Err("Unexpected response from server".to_string()),
         }
-        
-        app.set_socket(socket);
-
-        let encrypted_password = encrypion::encrypt_password(self.password.as_str());
-        app.socket.as_mut().unwrap().send(Message::Text(format!("login:{}:{}", self.username, encrypted_password)))
-        .await.expect("Failed to send message");
-
-        let response = app.socket.as_mut().unwrap().next().await.expect("Failed to receive message").unwrap();
-        if response.to_text().unwrap() == "login:success" {
-            let chat_frame = ChatFrame::new();
-            app.username = self.username.clone();
-            app.change_state(AppState::Chat(chat_frame));
+    }
+
+    async fn enter_chat(&self, app: &mut App, mut socket: Socket, username: String, session: SessionToken) {
+        let mut chat_frame = ChatFrame::new(username.clone());
+        if let Ok(ServerFrame::History { messages, .. }) = connection::read_frame(&mut socket).await {
+            chat_frame.messages = messages;
+        }
+        if self.remember {
+            let remembered = RememberedSession { server: app.server.url.clone(), username: username.clone(), session: session.clone() };
+            if let Err(err) = session_store::save(app.profile.as_deref(), &remembered) {
+                chat_frame.status = Some(err);
+            }
         } else {
-            return Err(response.to_text().unwrap().to_owned());
+            session_store::delete(app.profile.as_deref());
         }
-        Ok(())
+        app.attach(socket);
+        app.username = username;
+        app.session = Some(session);
+        app.change_state(AppState::Chat(chat_frame));
     }
 
     pub fn toggle_password_visibility(&mut self) {
@@ -88,10 +152,10 @@ impl LoginFrame {
 }
 
 impl CustomFrame for LoginFrame {
-    fn render(&self, frame: &mut Frame) {
+    fn render(&self, frame: &mut Frame, ui: &UiOptions) {
         let size = frame.area();
             let width = 50;
-            let height = 6;
+            let height = 12;
             let x = (size.width.saturating_sub(width)) / 2;
             let y = (size.height.saturating_sub(height)) / 2;
             let login_area = Rect::new(x, y, width, height);
@@ -100,38 +164,41 @@ impl CustomFrame for LoginFrame {
                 .direction(layout::Direction::Vertical)
                 .constraints(
                     [
-                        Constraint::Min(5),
-                        Constraint::Min(5),
+                        Constraint::Length(3),
+                        Constraint::Length(3),
+                        Constraint::Length(3),
+                        Constraint::Length(3),
                     ]
                     .as_ref(),
                 )
                 .split(login_area);
 
+            let border_color = |focus: Focus| if self.focus == focus { ui.accent_color } else { Color::Reset.into() };
+
+            let server_text = Paragraph::new(self.server.as_str())
+                .block(Block::default().borders(Borders::ALL)
+                .fg(border_color(Focus::Server)).title("Server"));
+
             let login_text = Paragraph::new(self.username.as_str())
                 .block(Block::default().borders(Borders::ALL)
-                .fg(
-                    if self.focus {
-                        Color::Magenta
-                    } else {
-                        Color::Reset
-                    }
-                ).title("Username"));
+                .fg(border_color(Focus::Username)).title("Username"));
             
-            let masked_password = repeat('*').take(self.password.len()).collect::<String>();
+            let masked_password = "*".repeat(self.password.len());
             let password_text = Paragraph::new(
                 if self.password_visible {
                     self.password.as_str()
                 } else {
                     masked_password.as_str()
                 }
-            ).block(Block::default().borders(Borders::ALL).fg(if !self.focus {
-                Color::Magenta
-            } else {
-                Color::Reset
-            }).title("Password"));
+            ).block(Block::default().borders(Borders::ALL).fg(border_color(Focus::Password)).title("Password"));
 
-            frame.render_widget(login_text, outer_layout[0]);
-            frame.render_widget(password_text, outer_layout[1]);
+            let remember_text = Paragraph::new(if self.remember { "[x] Remember me" } else { "[ ] Remember me" })
+                .block(Block::default().borders(Borders::ALL).fg(border_color(Focus::Remember)));
+
+            frame.render_widget(server_text, outer_layout[0]);
+            frame.render_widget(login_text, outer_layout[1]);
+            frame.render_widget(password_text, outer_layout[2]);
+            frame.render_widget(remember_text, outer_layout[3]);
 
             let pass_visibility_info = Paragraph::new(
             if self.password_visible {
@@ -142,7 +209,7 @@ impl CustomFrame for LoginFrame {
             .alignment(Alignment::Center);
             frame.render_widget(pass_visibility_info, Rect::new(0, 0, size.width, 1));
             
-            let help_text = Paragraph::new("Press Tab to switch fields, Enter to submit, F1 to toggle password visibility and F2 to switch to Register")
+            let help_text = Paragraph::new("Press Tab to switch fields, Enter to submit, F1 to toggle password visibility, F2 to switch to Register and F3 for server profiles")
             .alignment(Alignment::Center);
 
             let bottom_y = size.height.saturating_sub(1); // Assuming the help text height is 3
@@ -160,6 +227,14 @@ impl CustomFrame for LoginFrame {
 
 impl Default for LoginFrame {
     fn default() -> Self {
-        Self::new()
+        Self::new(String::new(), String::new(), false)
     }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Server,
+    Username,
+    Password,
+    Remember,
 }
\ No newline at end of file
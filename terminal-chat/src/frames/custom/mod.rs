@@ -1,3 +1,4 @@
 pub mod chat_frame;
 pub mod login_frame;
+pub mod profiles_frame;
 pub mod register_frame;
\ No newline at end of file
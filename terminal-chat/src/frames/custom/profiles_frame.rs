@@ -0,0 +1,291 @@
+use std::path::PathBuf;
+
+use ratatui::{layout::{Alignment, Constraint, Direction, Layout, Rect}, style::{Color, Style, Stylize}, widgets::{Block, Borders, List, ListItem, ListState, Paragraph}, Frame};
+
+use crate::{app::App, config::{Profile, UiOptions}, frames::custom_frame::CustomFrame, session_store};
+
+#[derive(Default)]
+pub struct ProfilesFrame {
+    // Copy of the profiles in the config, saved back after every change
+    profiles: Vec<Profile>,
+    selected: usize,
+    editor: Option<ProfileEditor>,
+    pub error_message: Option<String>,
+}
+
+// The add and edit form, `index` is None for a new profile
+struct ProfileEditor {
+    index: Option<usize>,
+    name: String,
+    server: String,
+    username: String,
+    ca_file: String,
+    insecure: bool,
+    focus: Field,
+}
+
+impl ProfilesFrame {
+    pub fn new(profiles: Vec<Profile>, selected: Option<&str>) -> Self {
+        let selected = selected
+            .and_then(|name| profiles.iter().position(|profile| profile.name == name))
+            .unwrap_or(0);
+        Self {
+            profiles,
+            selected,
+            editor: None,
+            error_message: None,
+        }
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.editor.is_some()
+    }
+
+    pub fn input(&mut self, c: char, app: &mut App) {
+        if let Some(editor) = &mut self.editor {
+            editor.input(c);
+            return;
+        }
+        match c {
+            'a' => self.editor = Some(ProfileEditor::new(None, &Profile::default())),
+            'e' => {
+                if let Some(profile) = self.profiles.get(self.selected) {
+                    self.editor = Some(ProfileEditor::new(Some(self.selected), profile));
+                }
+            },
+            'd' if self.selected < self.profiles.len() => {
+                let removed = self.profiles.remove(self.selected);
+                // Nothing could reach a remembered token of the profile anymore, not even /logout
+                session_store::delete(Some(&removed.name));
+                self.selected = self.selected.min(self.profiles.len().saturating_sub(1));
+                self.save(app);
+            },
+            _ => {}
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if let Some(editor) = &mut self.editor {
+            editor.backspace();
+        }
+    }
+
+    pub fn change_focus(&mut self) {
+        if let Some(editor) = &mut self.editor {
+            editor.focus = editor.focus.next();
+        }
+    }
+
+    pub fn up(&mut self) {
+        match &mut self.editor {
+            Some(editor) => editor.focus = editor.focus.previous(),
+            None => self.selected = self.selected.saturating_sub(1),
+        }
+    }
+
+    pub fn down(&mut self) {
+        match &mut self.editor {
+            Some(editor) => editor.focus = editor.focus.next(),
+            None => self.selected = (self.selected + 1).min(self.profiles.len().saturating_sub(1)),
+        }
+    }
+
+    pub fn cancel_edit(&mut self) {
+        self.editor = None;
+        self.error_message = None;
+    }
+
+    // Saves the form, or logs in with the selected profile when there is no form open
+    pub async fn submit(&mut self, app: &mut App) {
+        let Some(editor) = &self.editor else {
+            match self.profiles.get(self.selected) {
+                Some(profile) => app.open_profile(Some(profile.clone())).await,
+                None => self.error_message = Some("There are no profiles yet, press a to add one".to_string()),
+            }
+            return;
+        };
+
+        let profile = editor.profile();
+        if let Err(err) = app.config.validate_profile(&profile, editor.index) {
+            self.error_message = Some(err);
+            return;
+        }
+        match editor.index {
+            Some(index) => {
+                let old = std::mem::replace(&mut self.profiles[index], profile);
+                let new = &self.profiles[index];
+                // A remembered token is only good for the server and the user it came from
+                if old.server != new.server || old.username != new.username {
+                    session_store::delete(Some(&old.name));
+                } else if old.name != new.name {
+                    session_store::rename(&old.name, &new.name);
+                }
+            },
+            None => {
+                self.profiles.push(profile);
+                self.selected = self.profiles.len() - 1;
+            },
+        }
+        self.editor = None;
+        self.save(app);
+    }
+
+    fn save(&mut self, app: &mut App) {
+        app.config.profiles = self.profiles.clone();
+        self.error_message = app.config.save_profiles().err().map(|err| err.to_string());
+    }
+
+    fn render_list(&self, frame: &mut Frame, ui: &UiOptions, area: Rect) {
+        let items = self.profiles.iter()
+            .map(|profile| {
+                let username = if profile.username.is_empty() { String::new() } else { format!("{}@", profile.username) };
+                ListItem::new(format!("{:<16} {}{}", profile.name, username, profile.server))
+            })
+            .collect::<Vec<ListItem>>();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Profiles").fg(ui.accent_color))
+            .highlight_style(Style::default().fg(Color::Black).bg(ui.accent_color))
+            .highlight_symbol("> ");
+
+        let mut state = ListState::default().with_selected(Some(self.selected).filter(|_| !self.profiles.is_empty()));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+}
+
+impl ProfileEditor {
+    fn new(index: Option<usize>, profile: &Profile) -> Self {
+        Self {
+            index,
+            name: profile.name.clone(),
+            server: if profile.server.is_empty() { "ws://".to_string() } else { profile.server.clone() },
+            username: profile.username.clone(),
+            ca_file: profile.ca_file.as_ref().map(|path| path.display().to_string()).unwrap_or_default(),
+            insecure: profile.insecure,
+            focus: Field::Name,
+        }
+    }
+
+    fn input(&mut self, c: char) {
+        match self.focus {
+            Field::Name => self.name.push(c),
+            Field::Server => self.server.push(c),
+            Field::Username => self.username.push(c),
+            Field::CaFile => self.ca_file.push(c),
+            // Any key flips the checkbox
+            Field::Insecure => self.insecure = !self.insecure,
+        }
+    }
+
+    fn backspace(&mut self) {
+        match self.focus {
+            Field::Name => { self.name.pop(); },
+            Field::Server => { self.server.pop(); },
+            Field::Username => { self.username.pop(); },
+            Field::CaFile => { self.ca_file.pop(); },
+            Field::Insecure => self.insecure = false,
+        }
+    }
+
+    fn profile(&self) -> Profile {
+        let ca_file = self.ca_file.trim();
+        Profile {
+            name: self.name.trim().to_string(),
+            server: self.server.trim().to_string(),
+            username: self.username.trim().to_string(),
+            ca_file: (!ca_file.is_empty()).then(|| PathBuf::from(ca_file)),
+            insecure: self.insecure,
+        }
+    }
+
+    fn render(&self, frame: &mut Frame, ui: &UiOptions, area: Rect) {
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3); 5].as_ref())
+            .split(area);
+
+        let insecure = if self.insecure { "[x] accept any certificate" } else { "[ ] accept any certificate" };
+        let fields = [
+            (Field::Name, "Name", self.name.as_str()),
+            (Field::Server, "Server", self.server.as_str()),
+            (Field::Username, "Username", self.username.as_str()),
+            (Field::CaFile, "CA file", self.ca_file.as_str()),
+            (Field::Insecure, "Insecure", insecure),
+        ];
+        for (index, (field, title, value)) in fields.into_iter().enumerate() {
+            let paragraph = Paragraph::new(value)
+                .block(Block::default().borders(Borders::ALL)
+                .fg(if self.focus == field { ui.accent_color } else { Color::Reset })
+                .title(title));
+            frame.render_widget(paragraph, layout[index]);
+        }
+    }
+}
+
+impl CustomFrame for ProfilesFrame {
+    fn render(&self, frame: &mut Frame, ui: &UiOptions) {
+        let size = frame.area();
+        let width = 70.min(size.width);
+        let height = 15.min(size.height.saturating_sub(4));
+        let x = (size.width.saturating_sub(width)) / 2;
+        let y = (size.height.saturating_sub(height)) / 2;
+        let area = Rect::new(x, y, width, height);
+
+        let (title, help) = match &self.editor {
+            Some(editor) => (
+                if editor.index.is_some() { "Edit profile" } else { "New profile" },
+                "Press Tab or Up/Down to switch fields, Enter to save and Esc to cancel",
+            ),
+            None => (
+                "Choose a server",
+                "Press Enter to log in, a to add, e to edit, d to delete a profile, F3 to log in without one and Esc to quit",
+            ),
+        };
+
+        frame.render_widget(Paragraph::new(title).alignment(Alignment::Center), Rect::new(0, 0, size.width, 1));
+        match &self.editor {
+            Some(editor) => editor.render(frame, ui, area),
+            None => self.render_list(frame, ui, area),
+        }
+
+        let bottom_y = size.height.saturating_sub(1);
+        frame.render_widget(Paragraph::new(help).alignment(Alignment::Center), Rect::new(0, bottom_y, size.width, 1));
+
+        if let Some(error_msg) = self.error_message.clone() {
+            let error_text = Paragraph::new(error_msg)
+                .alignment(Alignment::Center)
+                .fg(Color::Red).bold();
+            frame.render_widget(error_text, Rect::new(0, 1, size.width, 1));
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Name,
+    Server,
+    Username,
+    CaFile,
+    Insecure,
+}
+
+impl Field {
+    fn next(self) -> Self {
+        match self {
+            Field::Name => Field::Server,
+            Field::Server => Field::Username,
+            Field::Username => Field::CaFile,
+            Field::CaFile => Field::Insecure,
+            Field::Insecure => Field::Name,
+        }
+    }
+
+    fn previous(self) -> Self {
+        match self {
+            Field::Name => Field::Insecure,
+            Field::Server => Field::Name,
+            Field::Username => Field::Server,
+            Field::CaFile => Field::Username,
+            Field::Insecure => Field::CaFile,
+        }
+    }
+}
@@ -1,11 +1,10 @@
-use std::iter::repeat;
-
-use futures_util::{SinkExt, StreamExt};
+use futures_util::SinkExt;
 use ratatui::{layout::{Alignment, Constraint, Direction, Layout, Rect}, style::{Color, Stylize}, widgets::{Block, Borders, Paragraph}};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use url::Url;
+use tokio_tungstenite::tungstenite::Message;
+
+use chat_protocol::{scram::{self, KdfParams}, ClientFrame, ServerFrame};
 
-use crate::{app::{App, AppState}, encrypion, frames::custom_frame::CustomFrame};
+use crate::{app::{App, AppState}, config::UiOptions, connection, frames::custom_frame::CustomFrame};
 
 use super::chat_frame::ChatFrame;
 
@@ -19,13 +18,13 @@ pub struct RegisterFrame {
 }
 
 impl RegisterFrame {
-    pub fn new() -> Self {
+    pub fn new(password_visible: bool) -> Self {
         Self {
             username: String::new(),
             password: String::new(),
             confirm_password: String::new(),
             error_message: None,
-            password_visible: false,
+            password_visible,
             focus: Focus::Username,
         }
     }
@@ -89,41 +88,32 @@ impl RegisterFrame {
             return Err("Passwords do not match".to_string());
         }
 
-        let url = Url::parse("ws://127.0.0.1:8080").unwrap();
-        let (mut socket, _) = match connect_async(url.as_str()).await {
-            Ok(result) => result,
-            Err(e) => return Err(format!("Failed to connect: {}", e)),
-        };
+        let mut socket = connection::connect(&app.server).await?;
 
-        if let Some(Ok(Message::Text(response))) = socket.next().await {
-            if response != "connection:success" {
-                return Err("Failed to establish connection".to_string());
-            }
-            app.set_socket(socket);
-        } else {
-            return Err("Failed to receive connection confirmation".to_string());
-        }
+        let credentials = scram::derive_credentials(self.password.as_str(), KdfParams::RECOMMENDED)?;
+        let register = ClientFrame::Register { username: self.username.clone(), credentials };
+        socket.send(Message::Text(register.encode())).await.map_err(|err| format!("Failed to send message => {err}"))?;
 
-        let encrypted_password = encrypion::encrypt_password(self.password.as_str());
-        app.socket.as_mut().unwrap().send(Message::Text(format!("register:{}:{}", self.username, encrypted_password))).await.expect("Failed to send message");
-        
-        if let Some(Ok(Message::Text(response))) = app.socket.as_mut().unwrap().next().await {
-            if response == "register:success" {
-                let chat_frame = ChatFrame::new();
-                app.username = self.username.clone();
+        match connection::read_frame(&mut socket).await? {
+            ServerFrame::Registered { username, session } => {
+                let mut chat_frame = ChatFrame::new(username.clone());
+                if let Ok(ServerFrame::History { messages, .. }) = connection::read_frame(&mut socket).await {
+                    chat_frame.messages = messages;
+                }
+                app.attach(socket);
+                app.username = username;
+                app.session = Some(session);
                 app.change_state(AppState::Chat(chat_frame));
-            } else {
-                return Err(response);
-            }
-        } else {
-            return Err("Failed to receive message".to_string());
+            },
+            ServerFrame::Error { message, .. } => return Err(message),
+            _ => return Err("Unexpected response from server".to_string()),
         }
         Ok(())
     }
 }
 
 impl CustomFrame for RegisterFrame {
-    fn render(&self, frame: &mut ratatui::Frame) {
+    fn render(&self, frame: &mut ratatui::Frame, ui: &UiOptions) {
         let size = frame.area();
         let width = 50;
         let height = 10;
@@ -147,13 +137,13 @@ impl CustomFrame for RegisterFrame {
                 .block(Block::default().borders(Borders::ALL)
                 .fg(
             if let Focus::Username = self.focus {
-                        Color::Magenta
+                        ui.accent_color
                     } else {
                         Color::Reset
                     }
                 ).title("Username"));
             
-                let masked_password = repeat('*').take(self.password.len()).collect::<String>();
+                let masked_password = "*".repeat(self.password.len());
                 let password_text = Paragraph::new(
             if self.password_visible {
                     self.password.as_str()
@@ -162,14 +152,14 @@ impl CustomFrame for RegisterFrame {
                 }
             ).block(Block::default().borders(Borders::ALL).fg(
             if let Focus::Password = self.focus {
-                Color::Magenta
+                ui.accent_color
             } else {
                 Color::Reset
             }).title("Password"));
 
 
             
-            let masked_confirm_password = repeat('*').take(self.confirm_password.len()).collect::<String>();
+            let masked_confirm_password = "*".repeat(self.confirm_password.len());
             let confirm_password_text = Paragraph::new(
                 if self.password_visible {
                     self.confirm_password.as_str()
@@ -177,7 +167,7 @@ impl CustomFrame for RegisterFrame {
                     masked_confirm_password.as_str()
                 }
             ).block(Block::default().borders(Borders::ALL).fg(if let Focus::ConfirmPassword = self.focus {
-                Color::Magenta
+                ui.accent_color
             } else {
                 Color::Reset
             }).title("Confirm Password"));
@@ -212,7 +202,7 @@ impl CustomFrame for RegisterFrame {
 
 impl Default for RegisterFrame {
     fn default() -> Self {
-        Self::new()
+        Self::new(false)
     }
 }
 
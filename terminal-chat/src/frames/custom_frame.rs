@@ -1,5 +1,7 @@
 use ratatui::Frame;
 
+use crate::config::UiOptions;
+
 pub trait CustomFrame {
-    fn render(&self, frame: &mut Frame);
-}
\ No newline at end of file
+    fn render(&self, frame: &mut Frame, ui: &UiOptions);
+}
@@ -1,13 +1,33 @@
 use app::App;
+use clap::Parser;
+use config::{Args, ClientConfig};
 
 mod app;
+mod config;
+mod connection;
 mod frames;
 mod encrypion;
+mod reconnect;
+mod session_store;
 
 #[tokio::main]
 async fn main() {
+    let args = Args::parse();
+    let config = match ClientConfig::load(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
     let terminal = ratatui::init();
-    let mut app = App::new(terminal);
+    let mut app = App::new(terminal, config);
+    // A server given on the command line skips the picker
+    match args.profile.as_deref().and_then(|name| app.config.profile(name)) {
+        Some(profile) => app.open_profile(Some(profile.clone())).await,
+        None if args.server.is_none() && !app.config.profiles.is_empty() => app.open_profiles(),
+        None => app.open_profile(None).await,
+    }
 
     match app.run().await {
         Ok(_) => ratatui::restore(),
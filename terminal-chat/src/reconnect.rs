@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+// Backoff between attempts to get a dropped connection back, doubling up to the maximum
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+// The next attempt to resume the session after the connection dropped
+pub struct Reconnect {
+    attempt: u32,
+    at: Instant,
+    // Why we are disconnected, the latest failed attempt once there was one
+    pub reason: String,
+}
+
+impl Reconnect {
+    // `hint` is how long the server asked us to wait, e.g. while it restarts
+    pub fn start(reason: String, hint: Option<Duration>) -> Self {
+        Self {
+            attempt: 0,
+            at: Instant::now() + jittered(hint.unwrap_or(INITIAL_DELAY)),
+            reason,
+        }
+    }
+
+    pub fn retry(&self, reason: String) -> Self {
+        let attempt = self.attempt + 1;
+        let delay = INITIAL_DELAY.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_DELAY);
+        Self {
+            attempt,
+            at: Instant::now() + jittered(delay),
+            reason,
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    pub fn status(&self) -> String {
+        let remaining = self.at.saturating_duration_since(Instant::now());
+        format!("{}, reconnecting in {}s", self.reason, remaining.as_millis().div_ceil(1000))
+    }
+
+    // Resolves at the attempt or when the countdown in the status changes, whichever comes first
+    pub async fn tick(&self) {
+        let remaining = self.at.saturating_duration_since(Instant::now());
+        let to_next_second = Duration::from_millis((remaining.as_millis() % 1000) as u64);
+        let wait = if to_next_second.is_zero() { remaining.min(Duration::from_secs(1)) } else { to_next_second };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+// Up to half the delay on top, so clients dropped together by a restart don't all come back at once
+fn jittered(delay: Duration) -> Duration {
+    delay + delay.mul_f64(rand::random::<f64>() / 2.0)
+}
@@ -0,0 +1,248 @@
+use std::{env, fs, io::{self, Write}, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_protocol::SessionToken;
+use ring::{aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN}, hkdf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// "Remember me": the session token of a login, kept per profile so the next start can resume it.
+// Files are only readable by the user and encrypted with a key derived from this machine and
+// user account, a copied file is useless anywhere else
+
+const SALT_LEN: usize = 16;
+const KEY_INFO: &[u8] = b"terminal-chat remembered session v1";
+// Used for logins without a profile
+const DEFAULT_NAME: &str = "default";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RememberedSession {
+    // The token is only offered to the server it came from
+    pub server: String,
+    pub username: String,
+    pub session: SessionToken,
+}
+
+pub fn exists(profile: Option<&str>) -> bool {
+    path(profile).is_some_and(|path| path.exists())
+}
+
+// None if nothing is remembered, the file can't be decrypted or the token has expired
+pub fn load(profile: Option<&str>) -> Option<RememberedSession> {
+    let content = fs::read_to_string(path(profile)?).ok()?;
+    let plaintext = decrypt(content.trim(), name(profile).as_bytes())?;
+    let remembered = toml::from_str::<RememberedSession>(std::str::from_utf8(&plaintext).ok()?).ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_millis() as i64;
+    (remembered.session.expires_at > now).then_some(remembered)
+}
+
+pub fn save(profile: Option<&str>, remembered: &RememberedSession) -> Result<(), String> {
+    let path = path(profile).ok_or("No data dir to remember the session in")?;
+    let plaintext = toml::to_string(remembered).map_err(|err| format!("Failed to encode session => {err}"))?;
+    let content = encrypt(plaintext.as_bytes(), name(profile).as_bytes())?;
+    write_private(&path, &content).map_err(|err| format!("Failed to write {} => {err}", path.display()))
+}
+
+pub fn delete(profile: Option<&str>) {
+    if let Some(path) = path(profile) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+// Follows a renamed profile, the file is encrypted for the name so it can't just be moved
+pub fn rename(from: &str, to: &str) {
+    if let Some(remembered) = load(Some(from)) {
+        let _ = save(Some(to), &remembered);
+    }
+    delete(Some(from));
+}
+
+fn name(profile: Option<&str>) -> &str {
+    profile.unwrap_or(DEFAULT_NAME)
+}
+
+// $XDG_DATA_HOME/terminal-chat/sessions/<hash of the profile name>, falling back to ~/.local/share.
+// Hashed so any profile name makes a valid file name
+fn path(profile: Option<&str>) -> Option<PathBuf> {
+    let data_dir = match env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".local").join("share"),
+    };
+    let hash = Sha256::digest(name(profile).as_bytes());
+    let file = hash[..8].iter().map(|byte| format!("{byte:02x}")).collect::<String>();
+    Some(data_dir.join("terminal-chat").join("sessions").join(file))
+}
+
+// Nothing secret goes in, the point is that the key only comes out the same for this user on this machine
+fn key_material() -> Vec<u8> {
+    let machine_id = ["/etc/machine-id", "/var/lib/dbus/machine-id"].iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    let user = env::var("USER").or_else(|_| env::var("USERNAME")).unwrap_or_default();
+    let home = env::var("HOME").unwrap_or_default();
+    [machine_id.trim(), user.as_str(), home.as_str()].join("\0").into_bytes()
+}
+
+fn key(salt: &[u8]) -> Result<LessSafeKey, String> {
+    let key_material = key_material();
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(&key_material);
+    let okm = prk.expand(&[KEY_INFO], &CHACHA20_POLY1305)
+        .map_err(|_| "Failed to derive the session key".to_string())?;
+    Ok(LessSafeKey::new(UnboundKey::from(okm)))
+}
+
+// base64(salt | nonce | ciphertext and tag), `aad` ties the file to its profile
+fn encrypt(plaintext: &[u8], aad: &[u8]) -> Result<String, String> {
+    let salt = rand::random::<[u8; SALT_LEN]>();
+    let nonce = rand::random::<[u8; NONCE_LEN]>();
+    let mut sealed = plaintext.to_vec();
+    key(&salt)?
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut sealed)
+        .map_err(|_| "Failed to encrypt the session".to_string())?;
+    Ok(STANDARD.encode([salt.as_slice(), nonce.as_slice(), sealed.as_slice()].concat()))
+}
+
+fn decrypt(content: &str, aad: &[u8]) -> Option<Vec<u8>> {
+    let data = STANDARD.decode(content).ok()?;
+    if data.len() < SALT_LEN + NONCE_LEN + aead::MAX_TAG_LEN {
+        return None;
+    }
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    let mut sealed = sealed.to_vec();
+    let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+    let plaintext = key(salt).ok()?.open_in_place(nonce, Aad::from(aad), &mut sealed).ok()?;
+    Some(plaintext.to_vec())
+}
+
+// Owner only: 0700 for the directory, 0600 for the file, also when it already existed
+fn write_private(path: &Path, content: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    if let Some(dir) = path.parent() {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(dir)?;
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    use super::*;
+
+    // The store is found through XDG_DATA_HOME, tests that touch it take turns
+    static DATA_HOME: Mutex<()> = Mutex::new(());
+
+    // Points XDG_DATA_HOME at an empty directory of its own until the guard is dropped
+    struct DataHome {
+        dir: PathBuf,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl DataHome {
+        fn new(test: &str) -> Self {
+            let lock = DATA_HOME.lock().unwrap_or_else(PoisonError::into_inner);
+            let dir = env::temp_dir().join(format!("terminal-chat-{test}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            env::set_var("XDG_DATA_HOME", &dir);
+            Self { dir, _lock: lock }
+        }
+    }
+
+    impl Drop for DataHome {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn remembered(expires_in_ms: i64) -> RememberedSession {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+        RememberedSession {
+            server: "ws://localhost:9001".to_string(),
+            username: "alice".to_string(),
+            session: SessionToken { token: "secret-token".to_string(), expires_at: now + expires_in_ms },
+        }
+    }
+
+    #[test]
+    fn encryption_round_trip() {
+        let content = encrypt(b"remember me", b"work").unwrap();
+        assert_eq!(decrypt(&content, b"work").unwrap(), b"remember me");
+        // Fresh salt and nonce every time
+        assert_ne!(encrypt(b"remember me", b"work").unwrap(), content);
+    }
+
+    #[test]
+    fn tampered_or_foreign_files_are_rejected() {
+        let content = encrypt(b"remember me", b"work").unwrap();
+        assert!(decrypt(&content, b"home").is_none());
+
+        let mut data = STANDARD.decode(&content).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(decrypt(&STANDARD.encode(&data), b"work").is_none());
+        assert!(decrypt(&STANDARD.encode(&data[..SALT_LEN + NONCE_LEN]), b"work").is_none());
+        assert!(decrypt("not base64!", b"work").is_none());
+    }
+
+    #[test]
+    fn saved_sessions_load_only_for_their_profile() {
+        let _home = DataHome::new("profile");
+        let saved = remembered(60_000);
+        save(Some("work"), &saved).unwrap();
+        assert!(exists(Some("work")));
+        assert!(!exists(None));
+        let loaded = load(Some("work")).unwrap();
+        assert_eq!((loaded.server, loaded.username, loaded.session), (saved.server, saved.username, saved.session));
+
+        let content = fs::read_to_string(path(Some("work")).unwrap()).unwrap();
+        assert!(!content.contains("secret-token"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(path(Some("work")).unwrap()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        // Copied over to another profile it doesn't decrypt anymore
+        write_private(&path(Some("home")).unwrap(), &content).unwrap();
+        assert!(load(Some("home")).is_none());
+
+        delete(Some("work"));
+        assert!(!exists(Some("work")));
+        assert!(load(Some("work")).is_none());
+    }
+
+    #[test]
+    fn expired_sessions_are_not_loaded() {
+        let _home = DataHome::new("expired");
+        save(None, &remembered(-1)).unwrap();
+        assert!(exists(None));
+        assert!(load(None).is_none());
+    }
+
+    #[test]
+    fn rename_moves_the_session() {
+        let _home = DataHome::new("rename");
+        save(Some("work"), &remembered(60_000)).unwrap();
+        rename("work", "office");
+        assert!(!exists(Some("work")));
+        assert_eq!(load(Some("office")).unwrap().session.token, "secret-token");
+
+        // Nothing to move, nothing is created
+        rename("work", "elsewhere");
+        assert!(!exists(Some("elsewhere")));
+    }
+}